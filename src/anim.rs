@@ -1,54 +1,277 @@
-use std::{
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-    },
-    thread::{self, JoinHandle},
-    time::Duration,
-};
-
-use winit::event_loop::EventLoopProxy;
-
-/// Event emitted when the next frame in
-/// an image sequence should be shown.
-#[derive(Debug)]
-pub struct RequestNextFrame;
+use std::time::{Duration, Instant};
 
+use crate::img::Repeat;
+
+/// Available playback speed multipliers.
+const SPEEDS: [f64; 5] = [0.25, 0.5, 1., 2., 4.];
+const NORMAL_SPEED: usize = 2;
+
+/// The clock driving an image sequence.
+///
+/// The clock owns the current frame index. Each frame ends at an
+/// absolute deadline, which the event loop waits until before
+/// calling [`Animator::tick`]; frames that are already over by then
+/// are skipped, so timing doesn't drift when drawing is slow.
 pub struct Animator {
-    handle: Option<JoinHandle<()>>,
+    delays: Vec<f64>,
+    index: usize,
+    is_paused: bool,
+
+    /// How many times to play through, and how
+    /// many times we've played through so far.
+    repeat: Repeat,
+    plays: u32,
+
+    /// Set once all plays are done;
+    /// playback stops on the last frame.
+    is_finished: bool,
+
+    /// Index into `SPEEDS`.
+    speed: usize,
+
+    /// When the current frame ends, if playing.
+    deadline: Instant,
+}
+
+/// What happened when the clock was moved along.
+#[derive(Default)]
+pub struct Tick {
+    /// Whether a different frame should be shown.
+    pub is_changed: bool,
 
-    /// A flag indicating when the frame delay thread
-    /// should terminate.
-    is_running: Arc<AtomicBool>,
+    /// Whether the last frame finished (at least once).
+    pub is_played_through: bool,
 }
+
 impl Animator {
-    pub fn new(proxy: EventLoopProxy<RequestNextFrame>, delays: &[f64]) -> Self {
-        let is_running = Arc::new(AtomicBool::new(true));
-
-        // Setup a separate thread to handle frame
-        // delays/advancement.
-        let should_run = Arc::clone(&is_running);
-        let delays = delays.to_vec();
-        let handle = thread::spawn(move || {
-            'outer: while should_run.load(Ordering::SeqCst) {
-                for delay in &delays {
-                    thread::sleep(Duration::from_secs_f64(*delay));
-                    if proxy.send_event(RequestNextFrame).is_err() {
-                        break 'outer;
-                    }
+    pub fn new(delays: &[f64], repeat: Repeat) -> Self {
+        let mut animator = Self {
+            delays: delays.to_vec(),
+            index: 0,
+            is_paused: false,
+            repeat,
+            plays: 0,
+            is_finished: false,
+            speed: NORMAL_SPEED,
+            deadline: Instant::now(),
+        };
+        animator.restart_frame();
+        animator
+    }
+
+    /// Reset the play count, e.g. after manually
+    /// moving through a finished animation.
+    fn restart(&mut self) {
+        self.plays = 0;
+        self.is_finished = false;
+    }
+
+    /// Time the current frame from now, e.g. after it's changed.
+    fn restart_frame(&mut self) {
+        self.deadline = Instant::now() + self.delay();
+    }
+
+    /// How long the current frame should be shown for.
+    fn delay(&self) -> Duration {
+        Duration::from_secs_f64(self.delays[self.index] / SPEEDS[self.speed])
+    }
+
+    /// When the current frame ends, if it's playing.
+    pub fn deadline(&self) -> Option<Instant> {
+        (!self.is_paused && !self.is_finished).then_some(self.deadline)
+    }
+
+    /// Move on past every frame that's ended by `now`.
+    pub fn tick(&mut self, now: Instant) -> Tick {
+        let mut tick = Tick::default();
+        while let Some(deadline) = self.deadline()
+            && deadline <= now
+        {
+            if self.index + 1 == self.delays.len() {
+                self.plays += 1;
+                tick.is_played_through = true;
+                if let Repeat::Times(times) = self.repeat
+                    && self.plays >= times
+                {
+                    self.is_finished = true;
+                    break;
                 }
             }
-        });
+            self.index = (self.index + 1) % self.delays.len();
+            self.deadline = deadline + self.delay();
+            tick.is_changed = true;
+        }
+        tick
+    }
+
+    /// The index of the frame that should be shown.
+    pub fn frame(&self) -> usize {
+        self.index
+    }
 
-        Self {
-            is_running,
-            handle: Some(handle),
+    pub fn frame_count(&self) -> usize {
+        self.delays.len()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed]
+    }
+
+    /// Pause or resume; a finished animation
+    /// is played again from the start.
+    pub fn toggle_pause(&mut self) {
+        if self.is_finished {
+            self.restart();
+            self.index = 0;
+            self.is_paused = false;
+        } else {
+            self.is_paused = !self.is_paused;
         }
+        self.restart_frame();
+    }
+
+    /// Pause and step one frame forward or back.
+    pub fn step(&mut self, forward: bool) {
+        let len = self.delays.len();
+        self.restart();
+        self.is_paused = true;
+        self.index = if forward {
+            (self.index + 1) % len
+        } else {
+            (self.index + len - 1) % len
+        };
+    }
+
+    /// Jump to a frame, clamped to the last frame.
+    pub fn seek(&mut self, index: usize) {
+        self.index = index.min(self.delays.len() - 1);
+        self.restart_frame();
+    }
+
+    pub fn change_speed(&mut self, faster: bool) {
+        self.speed = if faster {
+            (self.speed + 1).min(SPEEDS.len() - 1)
+        } else {
+            self.speed.saturating_sub(1)
+        };
+        self.restart_frame();
     }
 }
-impl Drop for Animator {
-    fn drop(&mut self) {
-        self.is_running.store(false, Ordering::SeqCst);
-        self.handle.take().unwrap().join().unwrap();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An animator with frames of 100ms each, and when it started.
+    fn animator(frames: usize, repeat: Repeat) -> (Animator, Instant) {
+        let animator = Animator::new(&vec![0.1; frames], repeat);
+        let start = animator.deadline().unwrap() - Duration::from_millis(100);
+        (animator, start)
+    }
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn waits_for_deadline() {
+        let (mut anim, start) = animator(3, Repeat::Forever);
+        let tick = anim.tick(at(start, 99));
+        assert!(!tick.is_changed);
+        assert_eq!(anim.frame(), 0);
+
+        let tick = anim.tick(at(start, 100));
+        assert!(tick.is_changed && !tick.is_played_through);
+        assert_eq!(anim.frame(), 1);
+        assert_eq!(anim.deadline(), Some(at(start, 200)));
+    }
+
+    #[test]
+    fn skips_frames_that_ended() {
+        let (mut anim, start) = animator(5, Repeat::Forever);
+        let tick = anim.tick(at(start, 350));
+        assert!(tick.is_changed);
+        assert_eq!(anim.frame(), 3);
+
+        // Deadlines follow on from the skipped frames, not from now.
+        assert_eq!(anim.deadline(), Some(at(start, 400)));
+    }
+
+    #[test]
+    fn loops_forever() {
+        let (mut anim, start) = animator(3, Repeat::Forever);
+        let tick = anim.tick(at(start, 1050));
+        assert!(tick.is_played_through);
+        assert_eq!(anim.frame(), 1);
+        assert!(anim.deadline().is_some());
+    }
+
+    #[test]
+    fn plays_given_number_of_times() {
+        let (mut anim, start) = animator(3, Repeat::Times(2));
+
+        // The end of the first play.
+        let tick = anim.tick(at(start, 300));
+        assert!(tick.is_played_through);
+        assert_eq!(anim.frame(), 0);
+
+        // The last frame of the second play is left up.
+        let tick = anim.tick(at(start, 599));
+        assert!(!tick.is_played_through);
+        assert_eq!(anim.frame(), 2);
+        let tick = anim.tick(at(start, 600));
+        assert!(tick.is_played_through && !tick.is_changed);
+        assert_eq!(anim.frame(), 2);
+        assert_eq!(anim.deadline(), None);
+
+        let tick = anim.tick(at(start, 10_000));
+        assert!(!tick.is_changed && !tick.is_played_through);
+        assert_eq!(anim.frame(), 2);
+    }
+
+    #[test]
+    fn plays_once() {
+        let (mut anim, start) = animator(2, Repeat::Times(1));
+        let tick = anim.tick(at(start, 10_000));
+        assert!(tick.is_changed && tick.is_played_through);
+        assert_eq!(anim.frame(), 1);
+        assert_eq!(anim.deadline(), None);
+    }
+
+    #[test]
+    fn single_frame_finishes() {
+        let (mut anim, start) = animator(1, Repeat::Times(3));
+        let tick = anim.tick(at(start, 10_000));
+        assert!(tick.is_played_through);
+        assert_eq!(anim.frame(), 0);
+        assert_eq!(anim.deadline(), None);
+    }
+
+    #[test]
+    fn finished_animation_restarts() {
+        let (mut anim, start) = animator(2, Repeat::Times(1));
+        anim.tick(at(start, 10_000));
+        anim.toggle_pause();
+        assert_eq!(anim.frame(), 0);
+        assert!(!anim.is_paused());
+        assert!(anim.deadline().is_some());
+    }
+
+    #[test]
+    fn paused_animation_stays_put() {
+        let (mut anim, start) = animator(3, Repeat::Forever);
+        anim.toggle_pause();
+        assert_eq!(anim.deadline(), None);
+        let tick = anim.tick(at(start, 10_000));
+        assert!(!tick.is_changed);
+        assert_eq!(anim.frame(), 0);
+
+        anim.step(false);
+        assert_eq!(anim.frame(), 2);
+        assert!(anim.is_paused());
     }
 }
@@ -0,0 +1,102 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// A least-recently-used cache, capped by the total
+/// size of its values (e.g. their pixel data).
+pub struct Cache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    budget: usize,
+    used: usize,
+    clock: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    size: usize,
+    last_used: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Cache<K, V> {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V, size: usize) {
+        self.clock += 1;
+        let entry = Entry {
+            value,
+            size,
+            last_used: self.clock,
+        };
+        if let Some(prev) = self.entries.insert(key, entry) {
+            self.used -= prev.size;
+        }
+        self.used += size;
+    }
+
+    /// Evict least-recently-used entries (other than `protected` ones)
+    /// until there's room for `size` more bytes.
+    ///
+    /// Returns `false` if there isn't enough evictable space.
+    /// A value larger than the whole budget is still allowed
+    /// if it's not protecting anything, so that it can be shown.
+    pub fn make_room(&mut self, size: usize, protected: &HashSet<K>) -> bool {
+        while self.used + size > self.budget {
+            let lru = self
+                .entries
+                .iter()
+                .filter(|(key, _)| !protected.contains(*key))
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match lru {
+                Some(key) => {
+                    let entry = self.entries.remove(&key).unwrap();
+                    self.used -= entry.size;
+                }
+                None => return protected.is_empty(),
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = Cache::new(2);
+        cache.insert("a", 'a', 1);
+        cache.insert("b", 'b', 1);
+        assert_eq!(cache.get(&"a"), Some('a'));
+
+        assert!(cache.make_room(1, &HashSet::new()));
+        cache.insert("c", 'c', 1);
+        assert!(!cache.contains(&"b"));
+        assert!(cache.contains(&"a"));
+
+        // Protected entries are kept, even if it means not making room.
+        let protected = HashSet::from(["a", "c"]);
+        assert!(!cache.make_room(1, &protected));
+        assert!(cache.contains(&"a") && cache.contains(&"c"));
+    }
+}
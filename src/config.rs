@@ -0,0 +1,428 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+use toml::Spanned;
+use winit::keyboard::{KeyCode, ModifiersState};
+
+use crate::{Action, Filter, Repeat, SKIP_COUNT, img::DEFAULT_FRAME_BUDGET};
+
+/// A key along with the modifiers held with it.
+pub type Chord = (ModifiersState, KeyCode);
+
+/// Default key bindings; these can be overridden
+/// or removed (by binding to `"none"`) in the config file.
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("f", "toggle_fullscreen"),
+    ("e", "zoom_in"),
+    ("=", "zoom_in"),
+    ("h", "zoom_out"),
+    ("-", "zoom_out"),
+    ("0", "toggle_fit"),
+    ("up", "pan_up"),
+    ("down", "pan_down"),
+    ("right", "pan_right"),
+    ("left", "pan_left"),
+    ("'", "next_image"),
+    ("n", "next_image"),
+    (",", "prev_image"),
+    ("p", "prev_image"),
+    ("home", "first_image"),
+    ("end", "last_image"),
+    ("pagedown", "skip_forward"),
+    ("pageup", "skip_back"),
+    ("shift+;", "go_to"),
+    ("/", "search"),
+    ("g", "toggle_gallery"),
+    ("s", "toggle_slideshow"),
+    ("enter", "open_selected"),
+    ("m", "cycle_filter"),
+    ("c", "toggle_color_management"),
+    ("r", "rotate_cw"),
+    ("shift+r", "rotate_ccw"),
+    ("x", "flip_horizontal"),
+    ("y", "flip_vertical"),
+    ("i", "toggle_info"),
+    ("space", "toggle_pause"),
+    ("]", "next_frame"),
+    ("[", "prev_frame"),
+    ("shift+]", "speed_up"),
+    ("shift+[", "slow_down"),
+    ("ctrl+[", "first_frame"),
+    ("ctrl+]", "last_frame"),
+    ("escape", "close"),
+    ("q", "quit"),
+];
+
+const DEFAULT_SLIDESHOW_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Config {
+    pub(crate) bindings: HashMap<Chord, Action>,
+
+    /// Resampling filter used when zooming.
+    pub filter: Filter,
+
+    /// How many times animations play, overriding
+    /// their own loop counts.
+    pub repeat: Option<Repeat>,
+
+    /// Whether directories (including ones dropped
+    /// onto the window) are searched recursively.
+    pub recursive: bool,
+
+    /// How long each image is shown for in a slideshow.
+    pub slideshow_interval: Duration,
+
+    /// Whether to start a slideshow right away.
+    pub slideshow: bool,
+
+    /// Whether to shuffle the images when a slideshow starts.
+    pub shuffle: bool,
+
+    /// Whether to apply images' EXIF orientation.
+    pub auto_orient: bool,
+
+    /// Whether to convert colors from images' ICC profiles.
+    pub color_manage: bool,
+
+    /// ICC profile to convert colors to, instead of sRGB.
+    pub output_profile: Option<PathBuf>,
+
+    /// How much memory (in bytes) an animation's frames may take
+    /// before they're streamed rather than kept in memory.
+    pub frame_budget: usize,
+}
+impl Default for Config {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(chord, action)| {
+                let chord = parse_chord(chord).expect("Invalid default key");
+                let action = action.parse().expect("Invalid default action");
+                (chord, action)
+            })
+            .collect();
+        Self {
+            bindings,
+            filter: Filter::default(),
+            repeat: None,
+            recursive: false,
+            slideshow_interval: DEFAULT_SLIDESHOW_INTERVAL,
+            slideshow: false,
+            shuffle: false,
+            auto_orient: true,
+            color_manage: true,
+            output_profile: None,
+            frame_budget: DEFAULT_FRAME_BUDGET,
+        }
+    }
+}
+impl Config {
+    /// Load the config from the given path, or from
+    /// `$XDG_CONFIG_HOME/vu/config.toml` if it exists.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let file: ConfigFile = toml::from_str(contents)?;
+
+        let mut config = Self::default();
+        if let Some(filter) = file.filter {
+            config.filter = filter
+                .get_ref()
+                .parse()
+                .map_err(|err| anyhow::anyhow!("line {}: {err}", line_of(contents, &filter)))?;
+        }
+        // In the order they're written, so that a chord
+        // that's bound twice is reported where it's repeated.
+        let mut keys: Vec<_> = file.keys.into_iter().collect();
+        keys.sort_by_key(|(key, _)| key.span().start);
+        let mut bound_on = HashMap::new();
+        for (key, action) in keys {
+            let line = line_of(contents, &key);
+            let chord = parse_chord(key.get_ref())
+                .with_context(|| format!("line {line}: invalid key `{}`", key.get_ref()))?;
+            if let Some(first) = bound_on.insert(chord, line) {
+                bail!(
+                    "line {line}: `{}` is already bound on line {first}",
+                    key.get_ref()
+                );
+            }
+            if action.get_ref() == "none" {
+                config.bindings.remove(&chord);
+                continue;
+            }
+            let action = action.get_ref().parse().with_context(|| {
+                format!(
+                    "line {}: invalid action `{}`",
+                    line_of(contents, &action),
+                    action.get_ref()
+                )
+            })?;
+            config.bindings.insert(chord, action);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    filter: Option<Spanned<String>>,
+
+    /// Maps key chords (e.g. `"ctrl+q"`) to action names.
+    keys: BTreeMap<Spanned<String>, Spanned<String>>,
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("vu").join("config.toml"))
+}
+
+/// The 1-indexed line a spanned value starts on.
+fn line_of<T>(contents: &str, spanned: &Spanned<T>) -> usize {
+    let start = spanned.span().start.min(contents.len());
+    contents[..start].matches('\n').count() + 1
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "toggle_fullscreen" => Action::ToggleFullscreen,
+            "zoom_in" => Action::ZoomIn,
+            "zoom_out" => Action::ZoomOut,
+            "toggle_fit" => Action::ToggleFit,
+            "pan_up" => Action::PanUp,
+            "pan_down" => Action::PanDown,
+            "pan_right" => Action::PanRight,
+            "pan_left" => Action::PanLeft,
+            "cycle_filter" => Action::CycleFilter,
+            "toggle_color_management" => Action::ToggleColorManagement,
+            "rotate_cw" => Action::Rotate(true),
+            "rotate_ccw" => Action::Rotate(false),
+            "flip_horizontal" => Action::Flip(true),
+            "flip_vertical" => Action::Flip(false),
+            "toggle_info" => Action::ToggleInfo,
+            "next_image" => Action::ChangeImage(true),
+            "prev_image" => Action::ChangeImage(false),
+            "first_image" => Action::FirstImage,
+            "last_image" => Action::LastImage,
+            "skip_forward" => Action::SkipImages(SKIP_COUNT),
+            "skip_back" => Action::SkipImages(-SKIP_COUNT),
+            "go_to" => Action::GoToPrompt,
+            "search" => Action::SearchPrompt,
+            "toggle_gallery" => Action::ToggleGallery,
+            "open_selected" => Action::OpenSelected,
+            "toggle_slideshow" => Action::ToggleSlideshow,
+            "toggle_pause" => Action::TogglePause,
+            "next_frame" => Action::StepFrame(true),
+            "prev_frame" => Action::StepFrame(false),
+            "speed_up" => Action::ChangeSpeed(true),
+            "slow_down" => Action::ChangeSpeed(false),
+            "first_frame" => Action::SeekFrame(false),
+            "last_frame" => Action::SeekFrame(true),
+            "quit" => Action::Quit,
+            "close" => Action::Close,
+            _ => bail!("unknown action"),
+        })
+    }
+}
+
+/// Parse a key chord like `"ctrl+shift+q"`.
+///
+/// Keys are physical keys (on a US layout), so e.g. `:` is
+/// `"shift+;"`; as a shorthand, `+` (or `"plus"`) is `"shift+="`,
+/// and can have modifiers of its own, e.g. `"ctrl++"`.
+fn parse_chord(chord: &str) -> anyhow::Result<Chord> {
+    let (names, key) = match chord.strip_suffix("++") {
+        Some(names) => (names, "+"),
+        None if chord == "+" => ("", chord),
+        None => chord.rsplit_once('+').unwrap_or(("", chord)),
+    };
+    let mut modifiers = ModifiersState::empty();
+    if !names.is_empty() {
+        for name in names.split('+') {
+            modifiers |= match name.to_lowercase().as_str() {
+                "ctrl" | "control" => ModifiersState::CONTROL,
+                "shift" => ModifiersState::SHIFT,
+                "alt" => ModifiersState::ALT,
+                "super" | "logo" | "meta" => ModifiersState::SUPER,
+                "" => bail!("missing modifier before `+`"),
+                _ => bail!("unknown modifier `{name}`"),
+            };
+        }
+    }
+    match key.to_lowercase().as_str() {
+        "" => bail!("missing key"),
+        "+" | "plus" => Ok((modifiers | ModifiersState::SHIFT, KeyCode::Equal)),
+        _ => Ok((modifiers, parse_key(key)?)),
+    }
+}
+
+fn parse_key(name: &str) -> anyhow::Result<KeyCode> {
+    Ok(match name.to_lowercase().as_str() {
+        "a" => KeyCode::KeyA,
+        "b" => KeyCode::KeyB,
+        "c" => KeyCode::KeyC,
+        "d" => KeyCode::KeyD,
+        "e" => KeyCode::KeyE,
+        "f" => KeyCode::KeyF,
+        "g" => KeyCode::KeyG,
+        "h" => KeyCode::KeyH,
+        "i" => KeyCode::KeyI,
+        "j" => KeyCode::KeyJ,
+        "k" => KeyCode::KeyK,
+        "l" => KeyCode::KeyL,
+        "m" => KeyCode::KeyM,
+        "n" => KeyCode::KeyN,
+        "o" => KeyCode::KeyO,
+        "p" => KeyCode::KeyP,
+        "q" => KeyCode::KeyQ,
+        "r" => KeyCode::KeyR,
+        "s" => KeyCode::KeyS,
+        "t" => KeyCode::KeyT,
+        "u" => KeyCode::KeyU,
+        "v" => KeyCode::KeyV,
+        "w" => KeyCode::KeyW,
+        "x" => KeyCode::KeyX,
+        "y" => KeyCode::KeyY,
+        "z" => KeyCode::KeyZ,
+        "0" => KeyCode::Digit0,
+        "1" => KeyCode::Digit1,
+        "2" => KeyCode::Digit2,
+        "3" => KeyCode::Digit3,
+        "4" => KeyCode::Digit4,
+        "5" => KeyCode::Digit5,
+        "6" => KeyCode::Digit6,
+        "7" => KeyCode::Digit7,
+        "8" => KeyCode::Digit8,
+        "9" => KeyCode::Digit9,
+        "'" => KeyCode::Quote,
+        "," => KeyCode::Comma,
+        "." => KeyCode::Period,
+        "/" => KeyCode::Slash,
+        ";" => KeyCode::Semicolon,
+        "[" => KeyCode::BracketLeft,
+        "]" => KeyCode::BracketRight,
+        "\\" => KeyCode::Backslash,
+        "-" => KeyCode::Minus,
+        "=" => KeyCode::Equal,
+        "`" => KeyCode::Backquote,
+        "up" => KeyCode::ArrowUp,
+        "down" => KeyCode::ArrowDown,
+        "left" => KeyCode::ArrowLeft,
+        "right" => KeyCode::ArrowRight,
+        "escape" | "esc" => KeyCode::Escape,
+        "enter" | "return" => KeyCode::Enter,
+        "space" => KeyCode::Space,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "f1" => KeyCode::F1,
+        "f2" => KeyCode::F2,
+        "f3" => KeyCode::F3,
+        "f4" => KeyCode::F4,
+        "f5" => KeyCode::F5,
+        "f6" => KeyCode::F6,
+        "f7" => KeyCode::F7,
+        "f8" => KeyCode::F8,
+        "f9" => KeyCode::F9,
+        "f10" => KeyCode::F10,
+        "f11" => KeyCode::F11,
+        "f12" => KeyCode::F12,
+        _ => bail!("unknown key `{name}`"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(contents: &str) -> String {
+        match Config::parse(contents) {
+            Ok(_) => panic!("config should be invalid:\n{contents}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn binds_keys() {
+        let config = Config::parse(
+            "[keys]\n\
+             \"ctrl+shift+q\" = \"quit\"\n\
+             \"+\" = \"zoom_in\"\n\
+             \"ctrl++\" = \"zoom_out\"\n\
+             \"q\" = \"none\"\n",
+        )
+        .unwrap();
+        let action = |modifiers, key| config.bindings.get(&(modifiers, key)).copied();
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        assert!(matches!(
+            action(ctrl_shift, KeyCode::KeyQ),
+            Some(Action::Quit)
+        ));
+        assert!(matches!(
+            action(ModifiersState::SHIFT, KeyCode::Equal),
+            Some(Action::ZoomIn)
+        ));
+        assert!(matches!(
+            action(ctrl_shift, KeyCode::Equal),
+            Some(Action::ZoomOut)
+        ));
+        assert!(action(ModifiersState::empty(), KeyCode::KeyQ).is_none());
+    }
+
+    #[test]
+    fn reports_bad_key() {
+        assert_eq!(
+            parse_err("[keys]\nq = \"quit\"\n\"ctrl+nope\" = \"quit\"\n"),
+            "line 3: invalid key `ctrl+nope`"
+        );
+        assert_eq!(
+            parse_err("[keys]\n\"hyper+q\" = \"quit\"\n"),
+            "line 2: invalid key `hyper+q`"
+        );
+    }
+
+    #[test]
+    fn reports_bad_action() {
+        assert_eq!(
+            parse_err("[keys]\nq = \"quit\"\n\nw = \"explode\"\n"),
+            "line 4: invalid action `explode`"
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_binding() {
+        assert_eq!(
+            parse_err("[keys]\n\"ctrl+q\" = \"quit\"\n\"control+Q\" = \"zoom_in\"\n"),
+            "line 3: `control+Q` is already bound on line 2"
+        );
+        assert!(parse_err("[keys]\nq = \"quit\"\nq = \"zoom_in\"\n").contains("line 3"));
+    }
+}
@@ -0,0 +1,254 @@
+use std::{ops::Range, path::PathBuf};
+
+use crate::{
+    Action,
+    thumbs::{THUMB_SIZE, Thumbnailer},
+};
+
+/// Space around each thumbnail in the grid.
+const CELL_PADDING: u32 = 16;
+const CELL_SIZE: u32 = THUMB_SIZE + CELL_PADDING * 2;
+
+/// How far a single scroll wheel step scrolls, in pixels.
+const SCROLL_STEP: f64 = CELL_SIZE as f64 / 2.;
+
+/// The view's background color, in sRGB.
+const BACKGROUND: [u8; 4] = [25, 25, 25, 255];
+
+/// Background of the selected cell.
+const HIGHLIGHT: [u8; 4] = [90, 90, 90, 255];
+
+/// A scrollable grid of thumbnails for every image
+/// in the playlist, one of which is selected.
+///
+/// The grid is drawn on the CPU to a viewport-sized buffer.
+pub struct Gallery {
+    selected: usize,
+    len: usize,
+
+    /// How far the grid is scrolled down, in pixels.
+    scroll: u32,
+
+    /// Size of the view, in physical pixels.
+    viewport: (u32, u32),
+
+    /// Whether the grid needs to be redrawn.
+    is_dirty: bool,
+}
+impl Gallery {
+    pub fn new(selected: usize, len: usize, viewport: (u32, u32)) -> Self {
+        let mut gallery = Self {
+            selected: 0,
+            len,
+            scroll: 0,
+            viewport: (viewport.0.max(1), viewport.1.max(1)),
+            is_dirty: true,
+        };
+        gallery.select(selected);
+        gallery
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Mark the grid as needing to be redrawn,
+    /// e.g. because a thumbnail finished loading.
+    pub fn mark_dirty(&mut self) {
+        self.is_dirty = true;
+    }
+
+    /// Whether the grid needs to be redrawn, resetting the flag.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.is_dirty)
+    }
+
+    pub fn resize(&mut self, viewport: (u32, u32)) {
+        self.viewport = (viewport.0.max(1), viewport.1.max(1));
+        self.select(self.selected);
+    }
+
+    /// Update the number of images, e.g. when files are dropped.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        self.select(self.selected);
+    }
+
+    /// Select an image (clamped to the last one),
+    /// scrolling to it if needed.
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.len.saturating_sub(1));
+
+        let top = (self.selected / self.columns()) as u32 * CELL_SIZE;
+        let bottom = top + CELL_SIZE;
+        if top < self.scroll {
+            self.scroll = top;
+        } else if bottom > self.scroll + self.viewport.1 {
+            self.scroll = bottom - self.viewport.1;
+        }
+        self.scroll = self.scroll.min(self.max_scroll());
+        self.is_dirty = true;
+    }
+
+    /// Move the selection by a number of images, stopping at either end.
+    fn move_by(&mut self, offset: isize) {
+        let last = self.len.saturating_sub(1) as isize;
+        self.select((self.selected as isize + offset).clamp(0, last) as usize);
+    }
+
+    /// Scroll down by a number of pixels (up if negative).
+    fn scroll_by(&mut self, dy: f64) {
+        let scroll = (self.scroll as f64 + dy).round();
+        self.scroll = scroll.clamp(0., self.max_scroll() as f64) as u32;
+        self.is_dirty = true;
+    }
+
+    fn columns(&self) -> usize {
+        (self.viewport.0 / CELL_SIZE).max(1) as usize
+    }
+
+    fn max_scroll(&self) -> u32 {
+        let rows = self.len.div_ceil(self.columns()) as u32;
+        (rows * CELL_SIZE).saturating_sub(self.viewport.1)
+    }
+
+    /// Left edge of the grid, which is centered horizontally.
+    fn margin(&self) -> u32 {
+        self.viewport
+            .0
+            .saturating_sub(self.columns() as u32 * CELL_SIZE)
+            / 2
+    }
+
+    /// The images that are at least partly in view.
+    pub fn visible(&self) -> Range<usize> {
+        let columns = self.columns();
+        let first_row = (self.scroll / CELL_SIZE) as usize;
+        let last_row = (self.scroll + self.viewport.1).div_ceil(CELL_SIZE) as usize;
+        (first_row * columns).min(self.len)..(last_row * columns).min(self.len)
+    }
+
+    /// The image under a point in the view, if any.
+    fn index_at(&self, (x, y): (f64, f64)) -> Option<usize> {
+        let x = x - self.margin() as f64;
+        let y = y + self.scroll as f64;
+        if x < 0. || y < 0. {
+            return None;
+        }
+        let column = (x / CELL_SIZE as f64) as usize;
+        let row = (y / CELL_SIZE as f64) as usize;
+        let columns = self.columns();
+        let index = row * columns + column;
+        (column < columns && index < self.len).then_some(index)
+    }
+
+    /// Apply an action to the grid, returning it if it's
+    /// one the viewer should handle instead.
+    ///
+    /// Navigation moves the selection rather than changing
+    /// the image, and opening the selection is returned
+    /// as an [`Action::OpenImage`].
+    pub fn handle(&mut self, action: Action) -> Option<Action> {
+        let columns = self.columns() as isize;
+        match action {
+            Action::PanUp => self.move_by(-columns),
+            Action::PanDown => self.move_by(columns),
+            Action::PanLeft | Action::ChangeImage(false) => self.move_by(-1),
+            Action::PanRight | Action::ChangeImage(true) => self.move_by(1),
+            Action::FirstImage => self.select(0),
+            Action::LastImage => self.select(usize::MAX),
+
+            // Move by a page of rows at a time.
+            Action::SkipImages(count) => {
+                let rows = (self.viewport.1 / CELL_SIZE).max(1) as isize;
+                self.move_by(count.signum() * rows * columns);
+            }
+            Action::Scroll(steps) => self.scroll_by(-steps * SCROLL_STEP),
+            Action::PanBy(_, dy) => self.scroll_by(dy as f64),
+            Action::Click(position) => {
+                if let Some(index) = self.index_at(position) {
+                    self.select(index);
+                }
+            }
+
+            // Double-clicking opens the image too.
+            Action::OpenSelected | Action::ToggleFit => {
+                return (self.len > 0).then_some(Action::OpenImage(self.selected));
+            }
+            Action::Close => return Some(Action::ToggleGallery),
+            Action::ToggleFullscreen
+            | Action::ToggleInfo
+            | Action::ToggleGallery
+            | Action::Quit => return Some(action),
+            _ => {}
+        }
+        None
+    }
+
+    /// Draw the visible thumbnails, returning the
+    /// RGBA buffer and its size (the viewport size).
+    pub fn render(&self, paths: &[PathBuf], thumbs: &Thumbnailer) -> (Vec<u8>, (u32, u32)) {
+        let (vw, vh) = self.viewport;
+        let mut frame = BACKGROUND.repeat((vw * vh) as usize);
+        let columns = self.columns();
+        let margin = self.margin() as i64;
+        for index in self.visible() {
+            let x = margin + (index % columns) as i64 * CELL_SIZE as i64;
+            let y = (index / columns) as i64 * CELL_SIZE as i64 - self.scroll as i64;
+            if index == self.selected {
+                blend(
+                    &mut frame,
+                    self.viewport,
+                    (x, y),
+                    (CELL_SIZE, CELL_SIZE),
+                    |_, _| HIGHLIGHT,
+                );
+            }
+
+            // Centered in the cell.
+            if let Some(thumb) = paths.get(index).and_then(|path| thumbs.get(path)) {
+                let (w, h) = thumb.size();
+                let data = thumb.frame(0);
+                let origin = (
+                    x + (CELL_SIZE as i64 - w as i64) / 2,
+                    y + (CELL_SIZE as i64 - h as i64) / 2,
+                );
+                blend(&mut frame, self.viewport, origin, (w, h), |tx, ty| {
+                    let i = ((ty * w + tx) * 4) as usize;
+                    [data[i], data[i + 1], data[i + 2], data[i + 3]]
+                });
+            }
+        }
+        (frame, self.viewport)
+    }
+}
+
+/// Blend a rectangle of pixels (given by `pixel`) over an opaque
+/// frame, clipping whatever falls outside of it.
+fn blend(
+    frame: &mut [u8],
+    (vw, vh): (u32, u32),
+    (origin_x, origin_y): (i64, i64),
+    (width, height): (u32, u32),
+    pixel: impl Fn(u32, u32) -> [u8; 4],
+) {
+    for y in 0..height {
+        let fy = origin_y + y as i64;
+        if fy < 0 || fy >= vh as i64 {
+            continue;
+        }
+        for x in 0..width {
+            let fx = origin_x + x as i64;
+            if fx < 0 || fx >= vw as i64 {
+                continue;
+            }
+            let [r, g, b, a] = pixel(x, y);
+            let idx = ((fy * vw as i64 + fx) * 4) as usize;
+            let alpha = a as u32;
+            for (channel, src) in [r, g, b].into_iter().enumerate() {
+                let dst = frame[idx + channel] as u32;
+                frame[idx + channel] = ((src as u32 * alpha + dst * (255 - alpha)) / 255) as u8;
+            }
+        }
+    }
+}
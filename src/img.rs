@@ -1,34 +1,310 @@
-use std::{fs::File, io::BufReader, path::Path};
-
-use fast_image_resize::{
-    FilterType, ResizeAlg, ResizeOptions, Resizer,
-    images::{Image as FIRImage, ImageRef as FIRImageRef},
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Cursor, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Condvar, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
 };
+
+use anyhow::Context;
+use fast_image_resize::{FilterType, ResizeAlg, ResizeOptions, Resizer, images::Image as FIRImage};
 use image::{
-    AnimationDecoder, DynamicImage, GenericImageView, ImageBuffer, ImageDecoder, ImageResult,
-    RgbaImage,
-    codecs::{gif::GifDecoder, webp::WebPDecoder},
+    AnimationDecoder, DynamicImage, Frame, Frames, GenericImageView, ImageBuffer, ImageDecoder,
+    ImageError, ImageFormat, ImageReader, Limits, RgbaImage,
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    error::{
+        DecodingError, ImageFormatHint, LimitError, LimitErrorKind, UnsupportedError,
+        UnsupportedErrorKind,
+    },
+    metadata::LoopCount,
 };
+use qcms::{DataType, Intent, Profile, Transform};
+
+/// Size of the blank image shown in place
+/// of one that couldn't be loaded.
+const PLACEHOLDER_SIZE: (u32, u32) = (640, 360);
+
+/// Why an image couldn't be read.
+///
+/// This is cheap to clone so that failed loads can be cached.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The file couldn't be read or decoded.
+    Decode(Arc<ImageError>),
+
+    /// The image couldn't be resized to fit.
+    Resize(String),
+
+    /// The file is an animation without any frames.
+    NoFrames,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Decode(err) => err.fmt(f),
+            Error::Resize(msg) => write!(f, "Failed to resize image: {msg}"),
+            Error::NoFrames => f.write_str("Animation has no frames"),
+        }
+    }
+}
+impl std::error::Error for Error {}
+impl From<ImageError> for Error {
+    fn from(err: ImageError) -> Self {
+        Error::Decode(Arc::new(err))
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        ImageError::IoError(err).into()
+    }
+}
+impl From<gif::DecodingError> for Error {
+    fn from(err: gif::DecodingError) -> Self {
+        ImageError::Decoding(DecodingError::new(ImageFormat::Gif.into(), err)).into()
+    }
+}
+
+/// Frame delays shorter than this are treated as
+/// [`DEFAULT_DELAY`], as browsers do. Some animations
+/// rely on this, and would otherwise play far too fast.
+const MIN_DELAY: f64 = 0.02;
+const DEFAULT_DELAY: f64 = 0.1;
+
+/// Default for [`DecodeOptions::frame_budget`].
+pub const DEFAULT_FRAME_BUDGET: usize = 256 * 1024 * 1024;
+
+/// How many frames of a streamed animation are decoded ahead.
+const STREAM_AHEAD: usize = 8;
+
+/// Options for how images are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeOptions {
+    /// Rotate and flip still images as their
+    /// EXIF orientation says they should be.
+    pub auto_orient: bool,
+
+    /// Convert colors from images' embedded ICC
+    /// profiles to the output profile.
+    pub color_manage: bool,
+
+    /// How much memory an animation's frames may take, in bytes.
+    /// Longer animations are streamed: decoded as they're played,
+    /// a few frames at a time, rather than all kept in memory.
+    pub frame_budget: usize,
+}
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            auto_orient: true,
+            color_manage: true,
+            frame_budget: DEFAULT_FRAME_BUDGET,
+        }
+    }
+}
+
+/// The profile colors are converted to: sRGB, unless
+/// another was loaded with [`load_output_profile`].
+static OUTPUT_PROFILE: OnceLock<Box<Profile>> = OnceLock::new();
+
+fn output_profile() -> &'static Profile {
+    OUTPUT_PROFILE.get_or_init(|| {
+        let mut profile = Profile::new_sRGB();
+        profile.precache_output_transform();
+        profile
+    })
+}
+
+/// Convert colors to the given ICC profile (e.g. the display's)
+/// rather than sRGB. This must be called before any images are read.
+pub fn load_output_profile(path: &Path) -> anyhow::Result<()> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read ICC profile {}", path.display()))?;
+    let mut profile = Profile::new_from_slice(&data, false)
+        .with_context(|| format!("Invalid ICC profile {}", path.display()))?;
+    profile.precache_output_transform();
+    OUTPUT_PROFILE
+        .set(profile)
+        .map_err(|_| anyhow::anyhow!("Output profile was already set"))
+}
+
+/// A transform from an image's embedded ICC profile to the output
+/// profile, for the kind of pixels the profile describes.
+enum ColorTransform {
+    /// RGBA pixels, converted in place.
+    Rgb(Transform),
+
+    /// Gray and alpha pixels, converted to RGBA.
+    Gray(Transform),
+}
+impl ColorTransform {
+    /// Convert the colors of an RGBA buffer.
+    fn apply(&self, rgba: &mut [u8]) {
+        match self {
+            Self::Rgb(transform) => transform.apply(rgba),
+            Self::Gray(transform) => {
+                // Gray pixels are decoded with all three color
+                // channels the same, so any of them will do.
+                let gray: Vec<u8> = rgba
+                    .chunks_exact(4)
+                    .flat_map(|pixel| [pixel[0], pixel[3]])
+                    .collect();
+                transform.convert(&gray, rgba);
+            }
+        }
+    }
+
+    /// Convert the colors of a decoded image to RGBA.
+    fn convert(&self, img: &DynamicImage) -> RgbaImage {
+        match self {
+            Self::Rgb(transform) => {
+                let mut rgba = img.to_rgba8();
+                transform.apply(&mut rgba);
+                rgba
+            }
+            Self::Gray(transform) => {
+                let gray = img.to_luma_alpha8();
+                let mut rgba = RgbaImage::new(img.width(), img.height());
+                transform.convert(&gray, &mut rgba);
+                rgba
+            }
+        }
+    }
+}
+
+/// A transform from the image's embedded ICC profile to the output
+/// profile, if it has an RGB or gray one (others, e.g. CMYK,
+/// describe colors the decoder has already converted to RGB).
+fn color_transform(
+    decoder: &mut impl ImageDecoder,
+    options: DecodeOptions,
+) -> Option<ColorTransform> {
+    if !options.color_manage {
+        return None;
+    }
+    let icc = decoder.icc_profile().ok()??;
+    let input = Profile::new_from_slice(&icc, false)?;
+    let to_rgba = |ty| {
+        Transform::new_to(
+            &input,
+            output_profile(),
+            ty,
+            DataType::RGBA8,
+            Intent::Perceptual,
+        )
+    };
+
+    // The profile's color space is in its header.
+    match icc.get(16..20)? {
+        b"RGB " => to_rgba(DataType::RGBA8).map(ColorTransform::Rgb),
+        b"GRAY" => to_rgba(DataType::GrayA8).map(ColorTransform::Gray),
+        _ => None,
+    }
+}
+
+/// How many times an animation plays through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Forever,
+    Times(u32),
+}
+impl FromStr for Repeat {
+    type Err = String;
 
-/// We can have either a single image
-/// or a sequence of images (i.e. an animated gif).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forever" => Ok(Repeat::Forever),
+            _ => match s.parse() {
+                Ok(n) if n > 0 => Ok(Repeat::Times(n)),
+                _ => Err(format!(
+                    "invalid loop count `{s}`, expected `forever` or a positive number"
+                )),
+            },
+        }
+    }
+}
+
+/// We can have either a single image, a sequence of images
+/// (i.e. an animated gif, png or webp), or an image too large
+/// to display in one piece, split into tiles.
 pub enum Image {
     Single {
         data: Vec<u8>,
         size: (u32, u32),
+
+        /// The image's full size, which `size` may
+        /// have been scaled down from to fit.
+        source_size: (u32, u32),
     },
     Sequence {
-        frames: Vec<Vec<u8>>,
+        frames: SequenceFrames,
         delays: Vec<f64>,
-        index: usize,
+        repeat: Repeat,
+        size: (u32, u32),
+    },
+    Tiled {
+        pyramid: Arc<Pyramid>,
+
+        /// The whole image scaled down to fit, shown until (and
+        /// around) the tiles in view are ready.
+        preview: Vec<u8>,
         size: (u32, u32),
     },
 }
 impl Image {
+    /// A blank image to show in place of one that couldn't be loaded.
+    pub fn placeholder() -> Self {
+        let (width, height) = PLACEHOLDER_SIZE;
+        Image::Single {
+            data: vec![0; (width * height * 4) as usize],
+            size: PLACEHOLDER_SIZE,
+            source_size: PLACEHOLDER_SIZE,
+        }
+    }
+
     pub fn size(&self) -> (u32, u32) {
         match self {
             Self::Single { size, .. } => *size,
             Self::Sequence { size, .. } => *size,
+            Self::Tiled { size, .. } => *size,
+        }
+    }
+
+    /// The size of the original image, before it was scaled down.
+    pub fn source_size(&self) -> (u32, u32) {
+        match self {
+            Self::Single { source_size, .. } => *source_size,
+            Self::Sequence { size, .. } => *size,
+            Self::Tiled { pyramid, .. } => pyramid.size(0),
+        }
+    }
+
+    /// Whether the image was scaled down to fit, and so
+    /// only has a fraction of the original's detail.
+    pub fn is_reduced(&self) -> bool {
+        self.size() != self.source_size()
+    }
+
+    /// Approximate memory used by the decoded pixel data.
+    pub fn byte_size(&self) -> usize {
+        match self {
+            Self::Single { data, .. } => data.len(),
+            Self::Sequence {
+                frames: SequenceFrames::Cached(frames),
+                ..
+            } => frames.iter().map(Vec::len).sum(),
+            Self::Sequence {
+                frames: SequenceFrames::Streamed(stream),
+                ..
+            } => stream.byte_size(),
+            Self::Tiled {
+                pyramid, preview, ..
+            } => pyramid.byte_size() + preview.len(),
         }
     }
 
@@ -39,79 +315,795 @@ impl Image {
         }
     }
 
-    /// Get the next frame.
-    pub fn next_frame(&mut self) -> &[u8] {
+    /// How many times the image should play through.
+    pub fn repeat(&self) -> Repeat {
+        match self {
+            Image::Sequence { repeat, .. } => *repeat,
+            _ => Repeat::Times(1),
+        }
+    }
+
+    /// Get a frame's pixel data; a single image only has one frame.
+    ///
+    /// A tiled image's frame is its preview; its full-resolution
+    /// pixels are read a region at a time with [`Pyramid::load_region`].
+    /// A streamed sequence has none; see [`Image::with_frame`].
+    pub fn frame(&self, index: usize) -> &[u8] {
         match self {
             Self::Single { data, .. } => data,
-            Self::Sequence { index, frames, .. } => {
-                let data = &frames[*index % frames.len()];
-                *index += 1;
-                data
-            }
+            Self::Tiled { preview, .. } => preview,
+            Self::Sequence {
+                frames: SequenceFrames::Cached(frames),
+                ..
+            } => &frames[index % frames.len()],
+            Self::Sequence {
+                frames: SequenceFrames::Streamed(_),
+                ..
+            } => &[],
         }
     }
 
-    pub fn scaled(&self, scale: f32) -> Self {
+    /// Call `f` with a frame's pixel data, returning whether it
+    /// could be. A streamed frame that hasn't been decoded yet is
+    /// decoded next, and `ready` is called once it has been.
+    pub fn with_frame(
+        &self,
+        index: usize,
+        f: impl FnOnce(&[u8]),
+        ready: impl FnOnce() + Send + 'static,
+    ) -> bool {
         match self {
-            Image::Single { data, size } => {
-                let (data, size) = scale_image_fast(data, *size, scale);
-                Image::Single { data, size }
+            Self::Sequence {
+                frames: SequenceFrames::Streamed(stream),
+                ..
+            } => stream.with_frame(index, f, ready),
+            _ => {
+                f(self.frame(index));
+                true
             }
-            Image::Sequence {
-                frames,
-                delays,
-                index,
-                size,
-            } => {
-                let target_size = scale_size(*size, scale);
-                Image::Sequence {
-                    frames: frames
-                        .iter()
-                        .map(|frame| scale_image_fast(frame, *size, scale).0)
-                        .collect(),
-                    delays: delays.clone(),
-                    index: *index,
-                    size: target_size,
+        }
+    }
+}
+
+/// Where a sequence's frames come from.
+pub enum SequenceFrames {
+    /// Every frame, decoded up front.
+    Cached(Vec<Vec<u8>>),
+
+    /// Decoded as they're needed.
+    Streamed(Arc<FrameStream>),
+}
+
+/// The frames of an animation that's too long to keep in
+/// memory, decoded a few at a time on another thread.
+///
+/// Frames are decoded in order just ahead of the one being
+/// shown; going back (or looping) reopens the file and
+/// decodes from the start again.
+pub struct FrameStream {
+    shared: Arc<StreamShared>,
+    frame_size: usize,
+}
+
+struct StreamShared {
+    state: Mutex<StreamState>,
+
+    /// Notified whenever a frame is decoded or wanted,
+    /// so the decoding thread can wait for room ahead.
+    changed: Condvar,
+}
+
+struct StreamState {
+    /// Decoded frames along with their indices, oldest
+    /// first; the first is the one being shown.
+    frames: VecDeque<(usize, Vec<u8>)>,
+
+    /// The index of the frame being decoded next.
+    next: usize,
+
+    /// A frame being waited on; frames before
+    /// it are skipped rather than kept.
+    skip_to: Option<usize>,
+
+    /// Called once the frame being waited on is decoded.
+    ready: Option<Box<dyn FnOnce() + Send>>,
+
+    /// Whether to start again from the first frame.
+    rewind: bool,
+
+    /// Set if a frame couldn't be decoded,
+    /// which stops the stream.
+    is_failed: bool,
+
+    /// A flag indicating when the decoding thread
+    /// should terminate.
+    is_running: bool,
+}
+
+impl FrameStream {
+    /// Start streaming `len` frames of the given size
+    /// (and length in bytes) from an animation's file.
+    fn new(
+        path: PathBuf,
+        len: usize,
+        size: (u32, u32),
+        frame_size: usize,
+        transform: Option<ColorTransform>,
+    ) -> Self {
+        let shared = Arc::new(StreamShared {
+            state: Mutex::new(StreamState {
+                frames: VecDeque::new(),
+                next: 0,
+                skip_to: None,
+                ready: None,
+                rewind: false,
+                is_failed: false,
+                is_running: true,
+            }),
+            changed: Condvar::new(),
+        });
+        {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || run_stream(&shared, &path, len, size, transform));
+        }
+        Self { shared, frame_size }
+    }
+
+    /// Memory used by the frames decoded ahead.
+    fn byte_size(&self) -> usize {
+        (STREAM_AHEAD + 1) * self.frame_size
+    }
+
+    /// Call `f` with a frame's pixel data if it's been decoded,
+    /// returning whether it was. Frames before it are dropped to
+    /// make room for the ones after it.
+    ///
+    /// Otherwise, the frame is decoded next (skipping any in
+    /// between) and `ready` is called once it is; this replaces
+    /// any frame that was being waited on. If the stream has
+    /// failed, neither is called.
+    fn with_frame(
+        &self,
+        index: usize,
+        f: impl FnOnce(&[u8]),
+        ready: impl FnOnce() + Send + 'static,
+    ) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(pos) = state.frames.iter().position(|(i, _)| *i == index) {
+            state.frames.drain(..pos);
+            self.shared.changed.notify_all();
+            f(&state.frames[0].1);
+            return true;
+        }
+        if state.is_failed {
+            return false;
+        }
+
+        // It's cheaper to start over than to go all the way
+        // round to a frame that's already been passed.
+        if state.skip_to != Some(index) {
+            state.frames.clear();
+            state.skip_to = Some(index);
+            if index < state.next {
+                state.rewind = true;
+            }
+            self.shared.changed.notify_all();
+        }
+        state.ready = Some(Box::new(ready));
+        false
+    }
+}
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        // The thread isn't joined as it may be in the
+        // middle of decoding a frame; it'll exit after.
+        let mut state = self.shared.state.lock().unwrap();
+        state.is_running = false;
+        self.shared.changed.notify_all();
+    }
+}
+
+fn run_stream(
+    shared: &StreamShared,
+    path: &Path,
+    len: usize,
+    size: (u32, u32),
+    transform: Option<ColorTransform>,
+) {
+    let mut decoder: Option<Frames<'static>> = None;
+    loop {
+        {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if !state.is_running {
+                    return;
+                }
+                if state.rewind {
+                    state.rewind = false;
+                    state.next = 0;
+                    decoder = None;
+                }
+                if state.skip_to.is_some() || state.frames.len() <= STREAM_AHEAD {
+                    break;
+                }
+                state = shared.changed.wait(state).unwrap();
+            }
+        }
+
+        let mut frames = match decoder.take() {
+            Some(frames) => frames,
+            None => match open_frames(path) {
+                Ok(frames) => frames,
+                Err(err) => return fail_stream(shared, path, &err),
+            },
+        };
+        let data = match frames.next() {
+            Some(Ok(frame)) => frame_data(frame, transform.as_ref(), size),
+            Some(Err(err)) => Err(err.into()),
+            None => Err(Error::NoFrames),
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(err) => return fail_stream(shared, path, &err),
+        };
+
+        let mut state = shared.state.lock().unwrap();
+        if state.rewind {
+            // The frame's no longer wanted.
+            continue;
+        }
+        let index = state.next;
+        state.next = (index + 1) % len;
+
+        // The file's reopened to loop.
+        if state.next != 0 {
+            decoder = Some(frames);
+        }
+        if state.skip_to.is_none_or(|target| target == index) {
+            state.skip_to = None;
+            state.frames.push_back((index, data));
+            shared.changed.notify_all();
+            if let Some(ready) = state.ready.take() {
+                drop(state);
+                ready();
+            }
+        }
+    }
+}
+
+/// Stop a stream that can't be decoded any further.
+fn fail_stream(shared: &StreamShared, path: &Path, err: &Error) {
+    eprintln!("Error decoding frame from {}: {err}", path.display());
+    shared.state.lock().unwrap().is_failed = true;
+    shared.changed.notify_all();
+}
+
+/// Open an animation to decode its frames from the start.
+fn open_frames(path: &Path) -> Result<Frames<'static>, Error> {
+    if is_stdin(path) {
+        frames_of(ImageReader::new(Cursor::new(read_stdin()?)))
+    } else {
+        frames_of(ImageReader::open(path)?)
+    }
+}
+
+fn frames_of<R: BufRead + Seek + 'static>(
+    reader: ImageReader<R>,
+) -> Result<Frames<'static>, Error> {
+    let (reader, format) = detect_format(reader)?;
+    let limits = decode_limits(false);
+    match format {
+        ImageFormat::Gif => {
+            let mut decoder = GifDecoder::new(reader.into_inner())?;
+            set_limits(&mut decoder, &limits)?;
+            Ok(decoder.into_frames())
+        }
+        ImageFormat::Png => {
+            let mut decoder = PngDecoder::with_limits(reader.into_inner(), limits.clone())?;
+            set_limits(&mut decoder, &limits)?;
+            Ok(decoder.apng()?.into_frames())
+        }
+        ImageFormat::WebP => {
+            let mut decoder = WebPDecoder::new(reader.into_inner())?;
+            set_limits(&mut decoder, &limits)?;
+            Ok(decoder.into_frames())
+        }
+        _ => Err(Error::NoFrames),
+    }
+}
+
+/// Width and height of the tiles in a [`Pyramid`].
+pub const TILE_SIZE: u32 = 256;
+
+/// A huge image split into tiles at power-of-two levels of
+/// detail, so that only the tiles in view need to be touched
+/// to show part of it at any zoom level.
+///
+/// The first level is the full image; each after that is half
+/// the size of the one before, and is built the first time
+/// it's needed (from the previous level, not the full image).
+pub struct Pyramid {
+    levels: Vec<OnceLock<Level>>,
+    size: (u32, u32),
+
+    /// Whether a region is being loaded in the background.
+    is_loading: AtomicBool,
+}
+
+struct Level {
+    size: (u32, u32),
+    columns: u32,
+
+    /// RGBA pixel data for each tile, row by row;
+    /// tiles on the right and bottom edges may be smaller.
+    tiles: Vec<Vec<u8>>,
+}
+
+impl Pyramid {
+    /// Split an image into tiles, dropping it once they're built
+    /// so the pixels aren't held twice for any longer than needed.
+    fn new(img: RgbaImage) -> Self {
+        let size = img.dimensions();
+
+        // Down to the first level that fits in a single tile.
+        let last = (0..)
+            .position(|level| {
+                let (width, height) = level_size(size, level);
+                width.max(height) <= TILE_SIZE
+            })
+            .unwrap();
+        let levels: Vec<_> = (0..=last).map(|_| OnceLock::new()).collect();
+        let _ = levels[0].set(Level::from_image(&img));
+        drop(img);
+        Self {
+            levels,
+            size,
+            is_loading: AtomicBool::new(false),
+        }
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn size(&self, level: usize) -> (u32, u32) {
+        level_size(self.size, level)
+    }
+
+    /// Memory used by the levels built so far.
+    fn byte_size(&self) -> usize {
+        self.levels
+            .iter()
+            .filter_map(OnceLock::get)
+            .flat_map(|level| &level.tiles)
+            .map(Vec::len)
+            .sum()
+    }
+
+    fn level(&self, level: usize) -> &Level {
+        self.levels[level].get_or_init(|| self.level(level - 1).downsample())
+    }
+
+    /// Copy a range of tiles from a level into one RGBA buffer on
+    /// another thread (building the level and those before it first,
+    /// if they haven't been), calling `done` with it and its size.
+    ///
+    /// Only one region is loaded at a time; this does nothing if
+    /// another is already being loaded, so it should be asked for
+    /// again once that's done.
+    pub fn load_region(
+        self: &Arc<Self>,
+        level: usize,
+        columns: Range<u32>,
+        rows: Range<u32>,
+        done: impl FnOnce(Vec<u8>, (u32, u32)) + Send + 'static,
+    ) {
+        if self.is_loading.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let pyramid = Arc::clone(self);
+        thread::spawn(move || {
+            let (data, size) = pyramid.region(level, columns, rows);
+            pyramid.is_loading.store(false, Ordering::Release);
+            done(data, size);
+        });
+    }
+
+    /// The whole image scaled down to fit within `max_size`, from the
+    /// smallest level with at least as much detail, and its size.
+    fn preview(&self, max_size: (u32, u32)) -> Result<(Vec<u8>, (u32, u32)), Error> {
+        let size = fit_size(self.size, max_size);
+        let level = (0..self.levels.len())
+            .rev()
+            .find(|&level| {
+                let (width, height) = self.size(level);
+                width >= size.0 && height >= size.1
+            })
+            .unwrap_or(0);
+        let (width, height) = self.size(level);
+        let columns = 0..width.div_ceil(TILE_SIZE);
+        let rows = 0..height.div_ceil(TILE_SIZE);
+        let (data, level_size) = self.region(level, columns, rows);
+        if level_size == size {
+            Ok((data, size))
+        } else {
+            Ok((resize_rgba(data, level_size, size)?.into_raw(), size))
+        }
+    }
+
+    /// Copy a range of tiles from a level into one RGBA
+    /// buffer, returning it and its size.
+    fn region(&self, level: usize, columns: Range<u32>, rows: Range<u32>) -> (Vec<u8>, (u32, u32)) {
+        let level = self.level(level);
+        let (x0, y0) = (columns.start * TILE_SIZE, rows.start * TILE_SIZE);
+        let x1 = (columns.end * TILE_SIZE).min(level.size.0);
+        let y1 = (rows.end * TILE_SIZE).min(level.size.1);
+        let (width, height) = (x1 - x0, y1 - y0);
+
+        let mut data = vec![0; (width * height * 4) as usize];
+        for row in rows {
+            for column in columns.clone() {
+                let (tile_w, tile_h) = level.tile_size(column, row);
+                let tile = &level.tiles[(row * level.columns + column) as usize];
+                let x = column * TILE_SIZE - x0;
+                for y in 0..tile_h {
+                    let src = (y * tile_w * 4) as usize;
+                    let dst = (((row * TILE_SIZE - y0 + y) * width + x) * 4) as usize;
+                    let len = (tile_w * 4) as usize;
+                    data[dst..dst + len].copy_from_slice(&tile[src..src + len]);
                 }
             }
         }
+        (data, (width, height))
     }
 }
 
-/// Read frames from an animated format.
-fn read_frames<'a, D: AnimationDecoder<'a> + ImageDecoder>(decoder: D) -> Image {
-    let size = decoder.dimensions();
-    let decoded = decoder
-        .into_frames()
-        .collect_frames()
-        .expect("Failed to decode frames");
-    let (frames, delays): (Vec<_>, Vec<_>) = decoded
-        .into_iter()
-        .map(|f| {
-            let (num, den) = f.delay().numer_denom_ms();
-            let delay = (num as f64 / den as f64) / 1000.;
-            (f.into_buffer().into_raw(), delay)
+/// The size of a level of a pyramid for an image of the given size.
+fn level_size((width, height): (u32, u32), level: usize) -> (u32, u32) {
+    let scale = 1 << level;
+    (width.div_ceil(scale).max(1), height.div_ceil(scale).max(1))
+}
+
+impl Level {
+    /// Split an image into tiles, a row of pixels at a time.
+    fn from_image(img: &RgbaImage) -> Self {
+        let size = img.dimensions();
+        let data = img.as_raw();
+        let stride = size.0 as usize * 4;
+        Self::from_tiles(size, |(x, y), (tile_w, tile_h)| {
+            let mut tile = Vec::with_capacity((tile_w * tile_h * 4) as usize);
+            for y in y..y + tile_h {
+                let start = y as usize * stride + x as usize * 4;
+                tile.extend_from_slice(&data[start..start + tile_w as usize * 4]);
+            }
+            tile
+        })
+    }
+
+    /// The next level down, with each pixel
+    /// averaging a 2x2 block of this one's.
+    fn downsample(&self) -> Self {
+        let (max_x, max_y) = (self.size.0 - 1, self.size.1 - 1);
+        Self::from_fn(level_size(self.size, 1), |x, y| {
+            // Weighted by alpha, so that transparent
+            // pixels don't bleed their color.
+            let mut sum = [0u32; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let pixel = self.pixel((x * 2 + dx).min(max_x), (y * 2 + dy).min(max_y));
+                let alpha = pixel[3] as u32;
+                for channel in 0..3 {
+                    sum[channel] += pixel[channel] as u32 * alpha;
+                }
+                sum[3] += alpha;
+            }
+            if sum[3] == 0 {
+                return [0; 4];
+            }
+            let color = |channel: usize| ((sum[channel] + sum[3] / 2) / sum[3]) as u8;
+            [color(0), color(1), color(2), ((sum[3] + 2) / 4) as u8]
+        })
+    }
+
+    /// Build a level tile by tile from each pixel's color.
+    fn from_fn(size: (u32, u32), pixel: impl Fn(u32, u32) -> [u8; 4]) -> Self {
+        Self::from_tiles(size, |(x, y), (tile_w, tile_h)| {
+            let mut tile = Vec::with_capacity((tile_w * tile_h * 4) as usize);
+            for y in y..y + tile_h {
+                for x in x..x + tile_w {
+                    tile.extend(pixel(x, y));
+                }
+            }
+            tile
         })
-        .unzip();
+    }
+
+    /// Build a level from each tile's pixel data, given
+    /// the position of its top-left pixel and its size.
+    fn from_tiles(size: (u32, u32), tile: impl Fn((u32, u32), (u32, u32)) -> Vec<u8>) -> Self {
+        let columns = size.0.div_ceil(TILE_SIZE);
+        let rows = size.1.div_ceil(TILE_SIZE);
+        let mut level = Self {
+            size,
+            columns,
+            tiles: Vec::with_capacity((columns * rows) as usize),
+        };
+        for row in 0..rows {
+            for column in 0..columns {
+                let tile_size = level.tile_size(column, row);
+                let origin = (column * TILE_SIZE, row * TILE_SIZE);
+                level.tiles.push(tile(origin, tile_size));
+            }
+        }
+        level
+    }
+
+    fn tile_size(&self, column: u32, row: u32) -> (u32, u32) {
+        (
+            (self.size.0 - column * TILE_SIZE).min(TILE_SIZE),
+            (self.size.1 - row * TILE_SIZE).min(TILE_SIZE),
+        )
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let (column, row) = (x / TILE_SIZE, y / TILE_SIZE);
+        let (tile_w, _) = self.tile_size(column, row);
+        let tile = &self.tiles[(row * self.columns + column) as usize];
+        let i = (((y % TILE_SIZE) * tile_w + x % TILE_SIZE) * 4) as usize;
+        [tile[i], tile[i + 1], tile[i + 2], tile[i + 3]]
+    }
+}
 
-    Image::Sequence {
+/// How many times an animated PNG or WebP plays through.
+fn loop_repeat(loop_count: LoopCount) -> Repeat {
+    match loop_count {
+        LoopCount::Infinite => Repeat::Forever,
+        LoopCount::Finite(n) => Repeat::Times(n.get()),
+    }
+}
+
+/// Run `f` on a reader, then seek back to where it
+/// started (whether or not `f` succeeded).
+fn peek<R: Seek, T>(reader: &mut R, f: impl FnOnce(&mut R) -> T) -> Result<T, Error> {
+    let start = reader.stream_position()?;
+    let result = f(reader);
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(result)
+}
+
+/// A GIF's frame delays and how many times it plays through,
+/// read with the `gif` crate directly: it can skip over frames
+/// without decompressing them, and the `image` crate loops a
+/// GIF without a loop count forever.
+///
+/// GIF loop counts (from the NETSCAPE2.0 extension) are the number
+/// of times to play the animation *again*; without one it's played once.
+///
+/// The delays are `None` if the frames couldn't be read through
+/// (see [`read_frames`]); the loop count comes before them.
+fn gif_info(reader: &mut impl Read) -> Result<(Option<Vec<f64>>, Repeat), Error> {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(reader)?;
+    let repeat = match decoder.repeat() {
+        gif::Repeat::Infinite => Repeat::Forever,
+        gif::Repeat::Finite(n) => Repeat::Times(u32::from(n) + 1),
+    };
+    let mut delays = vec![];
+    loop {
+        match decoder.next_frame_info() {
+            Ok(Some(frame)) => delays.push(delay_secs(f64::from(frame.delay) / 100.)),
+            Ok(None) => return Ok((Some(delays), repeat)),
+            Err(_) => return Ok((None, repeat)),
+        }
+    }
+}
+
+/// An animated PNG's frame delays, read from its `fcTL` chunks
+/// without decoding any image data. A still PNG has none.
+fn apng_delays(reader: &mut (impl Read + Seek)) -> Result<Vec<f64>, Error> {
+    // Skip the signature.
+    reader.seek(SeekFrom::Current(8))?;
+    let mut is_animated = false;
+    let mut delays = vec![];
+    loop {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+
+        // Each chunk ends with a checksum.
+        let mut skip = i64::from(len) + 4;
+        match &header[4..] {
+            b"acTL" => is_animated = true,
+            b"fcTL" if len >= 26 => {
+                let mut control = [0; 26];
+                reader.read_exact(&mut control)?;
+                skip -= 26;
+                let num = u16::from_be_bytes([control[20], control[21]]);
+                let den = match u16::from_be_bytes([control[22], control[23]]) {
+                    // Hundredths of a second.
+                    0 => 100,
+                    den => den,
+                };
+                delays.push(delay_secs(f64::from(num) / f64::from(den)));
+            }
+            // An animation is declared before the image data.
+            b"IDAT" if !is_animated => return Ok(vec![]),
+            b"IEND" => return Ok(delays),
+            _ => {}
+        }
+        reader.seek(SeekFrom::Current(skip))?;
+    }
+}
+
+/// An animated WebP's frame delays, read from its `ANMF` chunks
+/// without decoding any image data. A still WebP has none.
+fn webp_delays(reader: &mut (impl Read + Seek)) -> Result<Vec<f64>, Error> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    let riff_len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let end = reader.stream_position()? - 4 + u64::from(riff_len);
+    let mut delays = vec![];
+    while reader.stream_position()? < end {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        // Chunks are padded to an even length.
+        let mut skip = i64::from(len) + i64::from(len % 2);
+        if &header[..4] == b"ANMF" && len >= 16 {
+            let mut frame = [0; 16];
+            reader.read_exact(&mut frame)?;
+            skip -= 16;
+            let ms = u32::from_le_bytes([frame[12], frame[13], frame[14], 0]);
+            delays.push(delay_secs(f64::from(ms) / 1000.));
+        }
+        reader.seek(SeekFrom::Current(skip))?;
+    }
+    Ok(delays)
+}
+
+/// Read frames from an animated format, resized to `size`
+/// (see [`fit_size`]), keeping them all in memory if they fit
+/// within the budget, and streaming them from `path` otherwise.
+///
+/// The frame delays are read up front if possible (see
+/// [`gif_info`], [`apng_delays`] and [`webp_delays`]), so it can be
+/// told how much memory they'd take without decoding them. If they
+/// couldn't be, they're taken from the decoded frames, which are
+/// kept until they run over the budget.
+fn read_frames<'a>(
+    decoder: impl AnimationDecoder<'a>,
+    path: &Path,
+    size: (u32, u32),
+    delays: Option<Vec<f64>>,
+    repeat: Repeat,
+    transform: Option<ColorTransform>,
+    budget: usize,
+) -> Result<Image, Error> {
+    let frame_size = rgba_len(size)?;
+    let fits = |len: usize| len.saturating_mul(frame_size) <= budget;
+    let (frames, delays) = match delays {
+        Some(delays) if delays.is_empty() => return Err(Error::NoFrames),
+        Some(delays) if !fits(delays.len()) => (None, delays),
+        _ => {
+            // The delays are taken from the frames themselves in
+            // case a damaged file has fewer than it seemed to.
+            let mut frames = Some(vec![]);
+            let mut delays = vec![];
+            for frame in decoder.into_frames() {
+                let frame = frame?;
+                delays.push(frame_delay(&frame));
+                if !fits(delays.len()) {
+                    frames = None;
+                }
+                if let Some(frames) = &mut frames {
+                    frames.push(frame_data(frame, transform.as_ref(), size)?);
+                }
+            }
+            if delays.is_empty() {
+                return Err(Error::NoFrames);
+            }
+            (frames, delays)
+        }
+    };
+    let frames = match frames {
+        Some(frames) => SequenceFrames::Cached(frames),
+        None => SequenceFrames::Streamed(Arc::new(FrameStream::new(
+            path.to_path_buf(),
+            delays.len(),
+            size,
+            frame_size,
+            transform,
+        ))),
+    };
+    Ok(Image::Sequence {
         frames,
         delays,
+        repeat,
         size,
-        index: 0,
+    })
+}
+
+/// How long a frame is shown for, in seconds.
+fn frame_delay(frame: &Frame) -> f64 {
+    let (num, den) = frame.delay().numer_denom_ms();
+    delay_secs((num as f64 / den as f64) / 1000.)
+}
+
+/// A frame delay in seconds, with ones that are
+/// too short replaced by [`DEFAULT_DELAY`].
+fn delay_secs(delay: f64) -> f64 {
+    if delay < MIN_DELAY {
+        DEFAULT_DELAY
+    } else {
+        delay
     }
 }
 
-fn resize(src: DynamicImage, (dst_width, dst_height): (u32, u32)) -> DynamicImage {
-    let src_width = src.width();
-    let src_height = src.height();
+/// The length of an image's RGBA pixel data, or an
+/// error if it's too large to be held in memory at all.
+fn rgba_len((width, height): (u32, u32)) -> Result<usize, Error> {
+    usize::try_from(width)
+        .ok()
+        .zip(usize::try_from(height).ok())
+        .and_then(|(width, height)| width.checked_mul(height)?.checked_mul(4))
+        .ok_or_else(|| {
+            ImageError::Limits(LimitError::from_kind(LimitErrorKind::InsufficientMemory)).into()
+        })
+}
+
+/// A frame's RGBA pixel data, with its colors
+/// converted and resized to the given size.
+fn frame_data(
+    frame: Frame,
+    transform: Option<&ColorTransform>,
+    size: (u32, u32),
+) -> Result<Vec<u8>, Error> {
+    let mut buffer = frame.into_buffer();
+    if let Some(transform) = transform {
+        transform.apply(&mut buffer);
+    }
+    let source_size = buffer.dimensions();
+    if source_size == size {
+        Ok(buffer.into_raw())
+    } else {
+        Ok(resize_rgba(buffer.into_raw(), source_size, size)?.into_raw())
+    }
+}
+
+/// The size to scale an image down to so that it fits
+/// within `max_size`, keeping its aspect ratio.
+fn fit_size(size: (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    let width_scale = max_width as f32 / size.0 as f32;
+    let height_scale = max_height as f32 / size.1 as f32;
+    let scale = width_scale.min(height_scale);
+    if scale < 1. {
+        let target_width = ((scale * size.0 as f32).round() as u32).max(1);
+        let target_height = ((scale * size.1 as f32).round() as u32).max(1);
+        (target_width, target_height)
+    } else {
+        size
+    }
+}
+
+fn resize(src: DynamicImage, dst_size: (u32, u32)) -> Result<DynamicImage, Error> {
+    let src_size = src.dimensions();
+    let resized = resize_rgba(src.into_rgba8().into_raw(), src_size, dst_size)?;
+    Ok(DynamicImage::ImageRgba8(resized))
+}
+
+fn resize_rgba(
+    data: Vec<u8>,
+    (src_width, src_height): (u32, u32),
+    (dst_width, dst_height): (u32, u32),
+) -> Result<RgbaImage, Error> {
     let src_image = FIRImage::from_vec_u8(
         src_width,
         src_height,
-        src.to_rgba8().into_raw(),
+        data,
         fast_image_resize::PixelType::U8x4,
     )
-    .unwrap();
+    .map_err(|err| Error::Resize(err.to_string()))?;
     let mut dst_image = FIRImage::new(dst_width, dst_height, src_image.pixel_type());
     let mut resizer = Resizer::new();
     resizer
@@ -120,83 +1112,584 @@ fn resize(src: DynamicImage, (dst_width, dst_height): (u32, u32)) -> DynamicImag
             &mut dst_image,
             &ResizeOptions::default().resize_alg(ResizeAlg::Convolution(FilterType::Hamming)),
         )
-        .unwrap();
+        .map_err(|err| Error::Resize(err.to_string()))?;
 
-    let image_buffer: RgbaImage =
-        ImageBuffer::from_raw(dst_width, dst_height, dst_image.into_vec())
-            .expect("Failed to convert resized buffer to ImageBuffer");
-    DynamicImage::ImageRgba8(image_buffer)
-}
-
-fn scale_image_fast(
-    image: &[u8],
-    (width, height): (u32, u32),
-    scale: f32,
-) -> (Vec<u8>, (u32, u32)) {
-    let target_size = scale_size((width, height), scale);
-    let src_image =
-        FIRImageRef::new(width, height, image, fast_image_resize::PixelType::U8x4).unwrap();
-    let mut dst_image = FIRImage::new(
-        target_size.0,
-        target_size.1,
-        fast_image_resize::PixelType::U8x4,
-    );
-    let mut resizer = Resizer::new();
-    resizer
-        .resize(
-            &src_image,
-            &mut dst_image,
-            &ResizeOptions::new().resize_alg(fast_image_resize::ResizeAlg::Nearest),
-        )
-        .unwrap();
+    ImageBuffer::from_raw(dst_width, dst_height, dst_image.into_vec())
+        .ok_or_else(|| Error::Resize("resized buffer has the wrong size".into()))
+}
 
-    (dst_image.into_vec(), target_size)
+/// Decode a still image, converting its colors and applying its
+/// EXIF orientation (before it's resized, so it fits the right way round).
+fn decode_still(
+    mut decoder: impl ImageDecoder,
+    options: DecodeOptions,
+) -> Result<DynamicImage, Error> {
+    let orientation = if options.auto_orient {
+        Some(decoder.orientation()?)
+    } else {
+        None
+    };
+    let transform = color_transform(&mut decoder, options);
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    if let Some(transform) = transform {
+        img = DynamicImage::ImageRgba8(transform.convert(&img));
+    }
+    if let Some(orientation) = orientation {
+        img.apply_orientation(orientation);
+    }
+    Ok(img)
 }
 
-fn scale_size((width, height): (u32, u32), scale: f32) -> (u32, u32) {
-    let width_new = (width as f32 * scale).round() as u32;
-    let height_new = (height as f32 * scale).round() as u32;
-    (width_new, height_new)
+/// Still images with a side longer than this are too large for some
+/// textures, so they're tiled as soon as they're decoded rather than
+/// scaled down to fit and decoded again when they're zoomed into.
+/// It's the largest texture side that wgpu adapters are guaranteed to support.
+const TILED_SIDE: u32 = 8192;
+
+/// Decode a still image and convert it to fit within `max_size`.
+///
+/// One that's too large for a texture (or at full resolution, larger
+/// than `max_size`) is decoded once and tiled, with a preview scaled
+/// down from the tiles. At full resolution, the preview is only a
+/// thumbnail: the scaled-down image is already being shown.
+fn read_still(
+    mut decoder: impl ImageDecoder,
+    max_size: (u32, u32),
+    options: DecodeOptions,
+    full_res: bool,
+) -> Result<Image, Error> {
+    let (width, height) = decoder.dimensions();
+    let tile_above = if full_res {
+        max_size
+    } else {
+        (TILED_SIDE, TILED_SIDE)
+    };
+    let is_tiled = width > tile_above.0 || height > tile_above.1;
+    set_limits(&mut decoder, &decode_limits(full_res || is_tiled))?;
+    let img = decode_still(decoder, options)?;
+    if !is_tiled {
+        return read_single(img, max_size);
+    }
+
+    let pyramid = Pyramid::new(img.into_rgba8());
+    let preview_size = if full_res {
+        (TILE_SIZE, TILE_SIZE)
+    } else {
+        max_size
+    };
+    let (preview, size) = pyramid.preview(preview_size)?;
+    Ok(Image::Tiled {
+        pyramid: Arc::new(pyramid),
+        preview,
+        size,
+    })
 }
 
-fn read_single(path: &Path, (max_width, max_height): (u32, u32)) -> ImageResult<Image> {
-    image::open(path).map(|mut img| {
-        // Resize to fit if needed.
-        let mut size = img.dimensions();
-        let width_scale = max_width as f32 / size.0 as f32;
-        let height_scale = max_height as f32 / size.1 as f32;
-        let scale = width_scale.min(height_scale);
-        if scale < 1. {
-            let target_width = (scale * size.0 as f32).round() as u32;
-            let target_height = (scale * size.1 as f32).round() as u32;
-            img = resize(img, (target_width, target_height));
-            size = img.dimensions();
-        }
-        let rgba = img.to_rgba8();
-        let pixels: Vec<u8> = rgba.into_raw();
-        Image::Single { data: pixels, size }
+/// Convert a decoded image, resizing it to fit if needed.
+fn read_single(mut img: DynamicImage, max_size: (u32, u32)) -> Result<Image, Error> {
+    let source_size = img.dimensions();
+    let size = fit_size(source_size, max_size);
+    if size != source_size {
+        img = resize(img, size)?;
+    }
+    Ok(Image::Single {
+        data: img.into_rgba8().into_raw(),
+        size,
+        source_size,
     })
 }
 
-pub fn read_image(path: &Path, max_size: (u32, u32)) -> ImageResult<Image> {
-    let ext = path.extension().and_then(|ext| ext.to_str());
-    match ext {
-        Some("gif") => {
-            let file = File::open(path).expect("Failed to read gif");
-            let reader = BufReader::new(file);
-            let decoder = GifDecoder::new(reader).expect("Failed to decode gif");
-            Ok(read_frames(decoder))
+/// The path standing in for stdin, e.g. `render | vu -`.
+pub const STDIN_PATH: &str = "-";
+
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+/// The contents of stdin, read in full on first use
+/// so that it can be decoded more than once.
+fn read_stdin() -> Result<&'static [u8], Error> {
+    static STDIN: OnceLock<io::Result<Vec<u8>>> = OnceLock::new();
+    let data = STDIN.get_or_init(|| {
+        let mut data = vec![];
+        io::stdin().lock().read_to_end(&mut data).map(|_| data)
+    });
+    match data {
+        Ok(data) => Ok(data),
+        Err(err) => Err(io::Error::new(err.kind(), err.to_string()).into()),
+    }
+}
+
+/// Detect an image's format from its contents (its magic bytes),
+/// with the file extension (if any) as a fallback.
+fn detect_format<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+) -> Result<(ImageReader<R>, ImageFormat), Error> {
+    let reader = reader.with_guessed_format()?;
+    match reader.format() {
+        Some(format) if format.reading_enabled() => Ok((reader, format)),
+        format => {
+            let hint = format.map_or(ImageFormatHint::Unknown, ImageFormatHint::Exact);
+            Err(
+                ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                    hint.clone(),
+                    UnsupportedErrorKind::Format(hint),
+                ))
+                .into(),
+            )
         }
-        Some("webp") => {
-            let file = File::open(path).expect("Failed to read webp");
-            let reader = BufReader::new(file);
-            let decoder = WebPDecoder::new(reader).expect("Failed to decode webp");
+    }
+}
+
+/// Whether the file is in a format we can decode.
+pub fn is_supported(path: &Path) -> bool {
+    ImageReader::open(path)
+        .map_err(Error::from)
+        .and_then(detect_format)
+        .is_ok()
+}
+
+/// Read an image from a file, or from stdin if the path is [`STDIN_PATH`].
+pub fn read_image(
+    path: &Path,
+    max_size: (u32, u32),
+    options: DecodeOptions,
+) -> Result<Image, Error> {
+    read(path, max_size, options, false)
+}
+
+/// Read an image without scaling it down; a still image
+/// larger than `max_size` is split into tiles instead.
+///
+/// A still image that's too large for a texture will have
+/// already been tiled by [`read_image`], so it needn't be read again.
+///
+/// Decoders may allocate more for this than for other reads
+/// (see [`FULL_RES_MAX_ALLOC`]).
+pub fn read_full_image(
+    path: &Path,
+    max_size: (u32, u32),
+    options: DecodeOptions,
+) -> Result<Image, Error> {
+    read(path, max_size, options, true)
+}
+
+fn read(
+    path: &Path,
+    max_size: (u32, u32),
+    options: DecodeOptions,
+    full_res: bool,
+) -> Result<Image, Error> {
+    if is_stdin(path) {
+        decode(
+            ImageReader::new(Cursor::new(read_stdin()?)),
+            path,
+            max_size,
+            options,
+            full_res,
+        )
+    } else {
+        decode(ImageReader::open(path)?, path, max_size, options, full_res)
+    }
+}
+
+/// Read an image scaled down to fit within a square of the
+/// given size; for sequences, only the first frame is used.
+pub fn read_thumbnail(path: &Path, side: u32) -> Result<Image, Error> {
+    if is_stdin(path) {
+        decode_thumbnail(ImageReader::new(Cursor::new(read_stdin()?)), side)
+    } else {
+        decode_thumbnail(ImageReader::open(path)?, side)
+    }
+}
+
+fn decode_thumbnail<R: BufRead + Seek>(reader: ImageReader<R>, side: u32) -> Result<Image, Error> {
+    let (reader, _) = detect_format(reader)?;
+    let mut decoder = reader.into_decoder()?;
+    set_limits(&mut decoder, &decode_limits(false))?;
+    let img = decode_still(decoder, DecodeOptions::default())?;
+    read_single(img, (side, side))
+}
+
+/// The most a decoder may allocate for an image that's read at
+/// full resolution or tiled, rather than rejecting it as too large.
+const FULL_RES_MAX_ALLOC: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Limits on decoding, so that a corrupt or hostile header gets an
+/// image rejected rather than a decoder trying to allocate more memory
+/// than there is. These are only raised for an image that's read at full
+/// resolution (which for a still image too large for a texture, is any read).
+///
+/// A decoder's output (one frame of an animation) is reserved up
+/// front by [`set_limits`]; how far its working buffers are limited
+/// beyond that depends on the decoder.
+fn decode_limits(full_res: bool) -> Limits {
+    let mut limits = Limits::default();
+    if full_res {
+        limits.max_alloc = Some(FULL_RES_MAX_ALLOC);
+    }
+    limits
+}
+
+/// Apply limits to a decoder, first checking that
+/// its decoded image fits within them.
+fn set_limits(decoder: &mut impl ImageDecoder, limits: &Limits) -> Result<(), Error> {
+    let mut limits = limits.clone();
+    limits.reserve(decoder.total_bytes())?;
+    decoder.set_limits(limits)?;
+    Ok(())
+}
+
+/// Decode an image, picking the decoder by its detected format
+/// so that animations are decoded regardless of the file name.
+fn decode<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    path: &Path,
+    max_size: (u32, u32),
+    options: DecodeOptions,
+    full_res: bool,
+) -> Result<Image, Error> {
+    let (mut reader, format) = detect_format(reader)?;
+    let limits = decode_limits(full_res);
+    reader.limits(limits.clone());
+    match format {
+        // Animations' delays are read up front without decoding
+        // their frames, to tell whether they'll fit within the budget
+        // (falling back to decoding them if they can't be).
+        ImageFormat::Gif => {
+            let mut reader = reader.into_inner();
+            let (delays, repeat) = peek(&mut reader, gif_info)??;
+            let mut decoder = GifDecoder::new(reader)?;
+            set_limits(&mut decoder, &limits)?;
+            let size = fit_size(decoder.dimensions(), max_size);
+            let transform = color_transform(&mut decoder, options);
+            read_frames(
+                decoder,
+                path,
+                size,
+                delays,
+                repeat,
+                transform,
+                options.frame_budget,
+            )
+        }
+        ImageFormat::Png => {
+            let mut reader = reader.into_inner();
+            let delays = peek(&mut reader, apng_delays)?.ok();
+            let mut decoder = PngDecoder::with_limits(reader, limits.clone())?;
+            if decoder.is_apng()? {
+                set_limits(&mut decoder, &limits)?;
+                let size = fit_size(decoder.dimensions(), max_size);
+                let transform = color_transform(&mut decoder, options);
+                let decoder = decoder.apng()?;
+                let repeat = loop_repeat(decoder.loop_count());
+                read_frames(
+                    decoder,
+                    path,
+                    size,
+                    delays,
+                    repeat,
+                    transform,
+                    options.frame_budget,
+                )
+            } else {
+                read_still(decoder, max_size, options, full_res)
+            }
+        }
+        ImageFormat::WebP => {
+            let mut reader = reader.into_inner();
+            let delays = peek(&mut reader, webp_delays)?.ok();
+            let mut decoder = WebPDecoder::new(reader)?;
             if decoder.has_animation() {
-                Ok(read_frames(decoder))
+                set_limits(&mut decoder, &limits)?;
+                let size = fit_size(decoder.dimensions(), max_size);
+                let transform = color_transform(&mut decoder, options);
+                let repeat = loop_repeat(decoder.loop_count());
+                read_frames(
+                    decoder,
+                    path,
+                    size,
+                    delays,
+                    repeat,
+                    transform,
+                    options.frame_budget,
+                )
             } else {
-                read_single(path, max_size)
+                read_still(decoder, max_size, options, full_res)
+            }
+        }
+        _ => read_still(reader.into_decoder()?, max_size, options, full_res),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A two-frame GIF with the given loop count, if any.
+    fn gif(repeat: Option<gif::Repeat>) -> Vec<u8> {
+        let mut data = vec![];
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut data, 2, 2, &[0, 0, 0, 255, 255, 255]).unwrap();
+            if let Some(repeat) = repeat {
+                encoder.set_repeat(repeat).unwrap();
             }
+            for delay in [1, 20] {
+                let mut frame = gif::Frame::from_indexed_pixels(2, 2, vec![0, 1, 1, 0], None);
+                frame.delay = delay;
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn gif_loop_counts() {
+        let info = |repeat| gif_info(&mut Cursor::new(gif(repeat))).unwrap();
+
+        // Too-short delays are replaced.
+        let (delays, repeat) = info(None);
+        assert_eq!(delays.unwrap(), [DEFAULT_DELAY, 0.2]);
+        assert_eq!(repeat, Repeat::Times(1));
+
+        // GIF loop counts are how many times to play again.
+        assert_eq!(info(Some(gif::Repeat::Finite(0))).1, Repeat::Times(1));
+        assert_eq!(info(Some(gif::Repeat::Finite(2))).1, Repeat::Times(3));
+        assert_eq!(info(Some(gif::Repeat::Infinite)).1, Repeat::Forever);
+    }
+
+    /// A PNG chunk; its checksum isn't checked when reading delays.
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(kind);
+        chunk.extend(data);
+        chunk.extend([0; 4]);
+        chunk
+    }
+
+    /// An `fcTL` chunk with the given delay fraction.
+    fn fctl(num: u16, den: u16) -> Vec<u8> {
+        let mut data = [0; 26];
+        data[20..22].copy_from_slice(&num.to_be_bytes());
+        data[22..24].copy_from_slice(&den.to_be_bytes());
+        png_chunk(b"fcTL", &data)
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(png_chunk(b"IHDR", &[0; 13]));
+        data.extend(chunks.concat());
+        data.extend(png_chunk(b"IEND", &[]));
+        data
+    }
+
+    #[test]
+    fn apng_frame_delays() {
+        let data = png(&[
+            png_chunk(b"acTL", &[0; 8]),
+            fctl(3, 100),
+            png_chunk(b"IDAT", &[0; 5]),
+            // No denominator means hundredths.
+            fctl(25, 0),
+            png_chunk(b"fdAT", &[0; 7]),
+            // Too short.
+            fctl(1, 1000),
+            png_chunk(b"fdAT", &[0; 7]),
+        ]);
+        let delays = apng_delays(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(delays, [0.03, 0.25, DEFAULT_DELAY]);
+
+        // Truncated.
+        let cut = &data[..data.len() - 20];
+        assert!(apng_delays(&mut Cursor::new(cut)).is_err());
+    }
+
+    #[test]
+    fn still_png_has_no_delays() {
+        let data = png(&[png_chunk(b"IDAT", &[0; 5])]);
+        assert!(apng_delays(&mut Cursor::new(&data)).unwrap().is_empty());
+
+        // Frame controls after the image data don't make it animated.
+        let data = png(&[png_chunk(b"IDAT", &[0; 5]), png_chunk(b"acTL", &[0; 8])]);
+        assert!(apng_delays(&mut Cursor::new(&data)).unwrap().is_empty());
+    }
+
+    /// A WebP chunk, padded to an even length.
+    fn webp_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
         }
-        _ => read_single(path, max_size),
+        chunk
+    }
+
+    /// An `ANMF` chunk with the given duration and some frame data.
+    fn anmf(ms: u32, data_len: usize) -> Vec<u8> {
+        let mut data = vec![0; 16 + data_len];
+        data[12..15].copy_from_slice(&ms.to_le_bytes()[..3]);
+        webp_chunk(b"ANMF", &data)
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let chunks = chunks.concat();
+        let mut data = b"RIFF".to_vec();
+        data.extend((chunks.len() as u32 + 4).to_le_bytes());
+        data.extend(b"WEBP");
+        data.extend(chunks);
+        data
+    }
+
+    #[test]
+    fn webp_frame_delays() {
+        let data = webp(&[
+            webp_chunk(b"VP8X", &[0; 10]),
+            webp_chunk(b"ANIM", &[0; 6]),
+            anmf(40, 3),
+            anmf(0x01_0000, 8),
+            anmf(10, 1),
+        ]);
+        let delays = webp_delays(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(delays, [0.04, 65.536, DEFAULT_DELAY]);
+
+        let still = webp(&[webp_chunk(b"VP8L", &[0; 9])]);
+        assert!(webp_delays(&mut Cursor::new(&still)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn damaged_webp_fails() {
+        let data = webp(&[webp_chunk(b"ANIM", &[0; 6]), anmf(40, 3)]);
+
+        // Truncated in the middle of a frame's header.
+        let cut = &data[..data.len() - 18];
+        assert!(webp_delays(&mut Cursor::new(cut)).is_err());
+
+        // With a RIFF length longer than the file.
+        let mut long = data.clone();
+        long[4..8].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(webp_delays(&mut Cursor::new(&long)).is_err());
+
+        // Trailing data past the RIFF length is ignored.
+        let mut trailing = data;
+        trailing.extend(anmf(40, 3));
+        assert_eq!(webp_delays(&mut Cursor::new(&trailing)).unwrap(), [0.04]);
+    }
+
+    #[test]
+    fn falls_back_to_decoded_delays() {
+        let data = gif(None);
+        let decoder = || GifDecoder::new(Cursor::new(&data)).unwrap();
+        let path = Path::new("unused.gif");
+        let frame_size = 2 * 2 * 4;
+
+        let image = read_frames(decoder(), path, (2, 2), None, Repeat::Times(1), None, 1024);
+        let image = image.unwrap();
+        assert_eq!(image.delays().unwrap(), [DEFAULT_DELAY, 0.2]);
+        assert!(matches!(
+            image,
+            Image::Sequence {
+                frames: SequenceFrames::Cached(_),
+                ..
+            }
+        ));
+
+        // Frames that don't fit are streamed.
+        let image = read_frames(
+            decoder(),
+            path,
+            (2, 2),
+            None,
+            Repeat::Times(1),
+            None,
+            frame_size,
+        );
+        let image = image.unwrap();
+        assert_eq!(image.delays().unwrap(), [DEFAULT_DELAY, 0.2]);
+        assert!(matches!(
+            image,
+            Image::Sequence {
+                frames: SequenceFrames::Streamed(_),
+                ..
+            }
+        ));
+    }
+
+    /// The headers of a 24-bit BMP, without any pixel data.
+    fn bmp_header((width, height): (u32, u32)) -> Vec<u8> {
+        let mut data = b"BM".to_vec();
+        data.extend(54u32.to_le_bytes()); // File size
+        data.extend([0; 4]);
+        data.extend(54u32.to_le_bytes()); // Pixel data offset
+        data.extend(40u32.to_le_bytes()); // Header size
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend(1u16.to_le_bytes()); // Planes
+        data.extend(24u16.to_le_bytes()); // Bits per pixel
+        data.extend([0; 24]);
+        data
+    }
+
+    #[test]
+    fn rejects_huge_images() {
+        let data = bmp_header((40_000, 40_000));
+        let result = decode(
+            ImageReader::new(Cursor::new(data)),
+            Path::new(STDIN_PATH),
+            (1000, 1000),
+            DecodeOptions::default(),
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::Decode(err)) if matches!(*err, ImageError::Limits(_))
+        ));
+    }
+
+    #[test]
+    fn tiles_images_too_large_for_a_texture() {
+        let img = RgbaImage::from_pixel(TILED_SIDE + 1, 2, image::Rgba([255, 0, 0, 255]));
+        let mut data = Cursor::new(vec![]);
+        img.write_to(&mut data, ImageFormat::Png).unwrap();
+
+        let image = decode(
+            ImageReader::new(Cursor::new(data.into_inner())),
+            Path::new(STDIN_PATH),
+            (1000, 1000),
+            DecodeOptions::default(),
+            false,
+        )
+        .unwrap();
+        let Image::Tiled { preview, size, .. } = &image else {
+            panic!("not tiled");
+        };
+        assert_eq!(image.source_size(), (TILED_SIDE + 1, 2));
+        assert_eq!(*size, (1000, 1));
+        assert_eq!(preview[..4], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_huge_animation_frames() {
+        // A GIF with a 65535x65535 screen, and a single 1x1 frame.
+        let mut data = b"GIF89a".to_vec();
+        data.extend([0xff, 0xff, 0xff, 0xff, 0, 0, 0]);
+        data.extend([0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0x80, 0, 0, 0, 0, 0, 0]);
+        data.extend([2, 2, 0x44, 0x01, 0, 0x3b]);
+
+        let result = decode(
+            ImageReader::new(Cursor::new(data)),
+            Path::new(STDIN_PATH),
+            (1000, 1000),
+            DecodeOptions::default(),
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::Decode(err)) if matches!(*err, ImageError::Limits(_))
+        ));
+
+        assert!(rgba_len((65_535, 65_535)).is_ok());
+        assert!(rgba_len((u32::MAX, u32::MAX)).is_err());
     }
 }
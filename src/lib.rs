@@ -1,37 +1,213 @@
 mod anim;
+mod cache;
+mod config;
+mod gallery;
 mod img;
+mod loader;
+mod paths;
+mod playlist;
+mod pool;
+mod render;
+mod slideshow;
+mod thumbs;
 mod view;
 
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub use config::Config;
+pub use img::Repeat;
+pub use paths::expand_paths;
+pub use playlist::{Change, Playlist};
+pub use render::Filter;
 
-use anim::{Animator, RequestNextFrame};
+use anim::{Animator, Tick};
+use gallery::Gallery;
+use img::{DecodeOptions, Image};
+use loader::{Loaded, Loader, Target};
+use slideshow::Slideshow;
+use thumbs::Thumbnailer;
 use view::{ImageView, ViewOpts};
 use winit::{
-    event::{ElementState, Event, KeyEvent, WindowEvent},
-    event_loop::{EventLoopBuilder, EventLoopProxy},
-    keyboard::{KeyCode, PhysicalKey},
+    event::{ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
     window::{Window, WindowBuilder, WindowLevel},
 };
 
+/// How many images on either side of the
+/// current one to decode ahead of time.
+const PREFETCH_COUNT: usize = 2;
+
+/// Memory limit for the decoded image cache, in bytes.
+const CACHE_BUDGET: usize = 512 * 1024 * 1024;
+
+/// Events sent to the event loop from other threads.
+#[derive(Debug)]
+enum UserEvent {
+    /// An image finished decoding in the background.
+    ImageLoaded(PathBuf),
+
+    /// A thumbnail for the gallery is ready.
+    ThumbLoaded(PathBuf),
+
+    /// A region of a tiled image's tiles has been
+    /// loaded in the background, to be shown.
+    TilesLoaded,
+
+    /// A streamed animation frame that was
+    /// asked for has finished decoding.
+    FrameDecoded,
+}
+
 struct Viewer {
     view: ImageView,
-
-    #[allow(unused)]
     animator: Option<Animator>,
+
+    /// Told when a streamed frame that wasn't ready is decoded.
+    proxy: EventLoopProxy<UserEvent>,
 }
 impl Viewer {
     fn new(
-        path: &Path,
+        image: Arc<Image>,
         window: &Window,
-        proxy: &EventLoopProxy<RequestNextFrame>,
+        proxy: &EventLoopProxy<UserEvent>,
         opts: ViewOpts,
+        repeat: Option<Repeat>,
     ) -> anyhow::Result<Self> {
-        let view = ImageView::new(path, window, opts)?;
+        let view = ImageView::new(image, window, proxy, opts)?;
+        let repeat = repeat.unwrap_or(view.image.repeat());
         let animator = view
             .image
             .delays()
-            .map(|delays| Animator::new(proxy.clone(), delays));
-        Ok(Self { view, animator })
+            .map(|delays| Animator::new(delays, repeat));
+        let mut viewer = Self {
+            view,
+            animator,
+            proxy: proxy.clone(),
+        };
+        viewer.sync_frame();
+        Ok(viewer)
+    }
+
+    /// Show another image, or, if it couldn't be loaded,
+    /// a placeholder showing the path and the reason why.
+    fn show(&mut self, loaded: Loaded, path: &Path, repeat: Option<Repeat>) {
+        let err = match loaded {
+            Ok(image) => match self.set_image(image, repeat) {
+                Ok(()) => return,
+                Err(err) => err,
+            },
+            Err(err) => err.into(),
+        };
+        eprintln!("Error loading image {}: {err}", path.display());
+        match self.set_image(Arc::new(Image::placeholder()), None) {
+            Ok(()) => self.set_message(Some(format!("{}\n{err}", path.display()))),
+            Err(err) => eprintln!("Error showing placeholder: {err}"),
+        }
+    }
+
+    fn set_image(&mut self, image: Arc<Image>, repeat: Option<Repeat>) -> anyhow::Result<()> {
+        self.view.set_image(image)?;
+        let repeat = repeat.unwrap_or(self.view.image.repeat());
+        self.animator = self
+            .view
+            .image
+            .delays()
+            .map(|delays| Animator::new(delays, repeat));
+        self.sync_frame();
+        Ok(())
+    }
+
+    /// Show the current image decoded again (e.g. with other colors),
+    /// keeping the zoom, pan, orientation and animation position.
+    ///
+    /// If it's changed shape (or couldn't be decoded),
+    /// it's shown afresh instead.
+    fn redecode(&mut self, loaded: Loaded, path: &Path, repeat: Option<Repeat>) {
+        let current = &self.view.image;
+        let image = match &loaded {
+            Ok(image)
+                if image.size() == current.size()
+                    && image.delays().map(<[f64]>::len) == current.delays().map(<[f64]>::len) =>
+            {
+                Arc::clone(image)
+            }
+            _ => return self.show(loaded, path, repeat),
+        };
+        if let Err(err) = self.view.replace_image(image) {
+            eprintln!("Error loading image {}: {err}", path.display());
+            return;
+        }
+        if self.animator.is_some() {
+            self.sync_frame();
+        } else {
+            self.view.show_frame(0, || {});
+        }
+        self.view.draw();
+    }
+
+    fn is_animated(&self) -> bool {
+        self.animator.is_some()
+    }
+
+    /// When the current frame of an image sequence ends.
+    fn deadline(&self) -> Option<Instant> {
+        self.animator.as_ref()?.deadline()
+    }
+
+    /// Move an image sequence on past any frames that have ended.
+    fn tick(&mut self, now: Instant) -> Tick {
+        self.animator
+            .as_mut()
+            .map_or_else(Tick::default, |animator| animator.tick(now))
+    }
+
+    /// Show the current frame of an image sequence.
+    ///
+    /// This never moves the animation along; only the animator's
+    /// clock does, so redrawing after a zoom, pan or resize shows
+    /// the same frame.
+    fn show_current_frame(&mut self) -> bool {
+        self.sync_frame();
+        self.view.draw()
+    }
+
+    /// Upload the animator's current frame
+    /// and show the playback state in the label.
+    ///
+    /// A streamed frame that isn't decoded yet is shown once a
+    /// [`UserEvent::FrameDecoded`] arrives; until then (or if
+    /// the animation moves on first) the last one stays up.
+    fn sync_frame(&mut self) {
+        if let Some(animator) = &self.animator {
+            let index = animator.frame();
+            let mut status = format!("{}/{}", index + 1, animator.frame_count());
+            let speed = animator.speed();
+            if speed != 1. {
+                status.push_str(&format!(" {speed}x"));
+            }
+            if animator.is_paused() {
+                status.push_str(" paused");
+            }
+            let proxy = self.proxy.clone();
+            self.view.show_frame(index, move || {
+                let _ = proxy.send_event(UserEvent::FrameDecoded);
+            });
+            self.view.set_status(Some(status));
+        }
+    }
+
+    /// Apply a playback control; does nothing
+    /// if the image isn't animated.
+    fn control(&mut self, f: impl FnOnce(&mut Animator)) {
+        if let Some(animator) = &mut self.animator {
+            f(animator);
+            self.show_current_frame();
+        }
     }
 }
 impl std::ops::DerefMut for Viewer {
@@ -47,13 +223,17 @@ impl std::ops::Deref for Viewer {
     }
 }
 
-pub fn run<P: AsRef<Path>>(
+pub fn run(
     title: &str,
-    image_paths: &[P],
+    mut playlist: Playlist,
     max_side: Option<u32>,
+    config: Config,
 ) -> anyhow::Result<()> {
-    if let Some(image_path) = image_paths.first() {
-        let event_loop = EventLoopBuilder::<RequestNextFrame>::with_user_event()
+    if let Some(path) = &config.output_profile {
+        img::load_output_profile(path)?;
+    }
+    if let Some(image_path) = playlist.current() {
+        let event_loop = EventLoopBuilder::<UserEvent>::with_user_event()
             .build()
             .expect("Failed to create event loop");
         let proxy = event_loop.create_proxy();
@@ -67,34 +247,100 @@ pub fn run<P: AsRef<Path>>(
             .build(&event_loop)
             .unwrap();
 
-        let mut index: usize = 0;
-        let label = format!(
-            "{} {}/{}",
-            image_path.as_ref().display(),
-            index + 1,
-            image_paths.len()
-        );
+        let workers = std::thread::available_parallelism().map_or(2, |n| n.get().min(4));
+        let loader = Loader::new(proxy.clone(), workers, CACHE_BUDGET);
+        let thumbnailer = Thumbnailer::new(proxy.clone(), workers);
+        let mut decode_target = Target {
+            bounds: view::max_bounds(&window, max_side),
+            options: DecodeOptions {
+                auto_orient: config.auto_orient,
+                color_manage: config.color_manage,
+                frame_budget: config.frame_budget,
+            },
+            full_res: false,
+        };
+
+        let label = image_label(&playlist, decode_target);
 
-        let mut image_view = Viewer::new(
-            image_path.as_ref(),
+        // The first image is decoded up front as
+        // the window is sized to fit it.
+        let loaded =
+            img::read_image(image_path, decode_target.bounds, decode_target.options).map(Arc::new);
+        loader.insert(image_path, decode_target, loaded.clone());
+        let mut image_view = load_viewer(
+            loaded,
+            image_path,
             &window,
             &proxy,
             ViewOpts {
-                max_side,
                 resize_window: true,
                 show_label: false,
                 label,
+                filter: config.filter,
+                prompt: None,
             },
+            config.repeat,
         )?;
+        prefetch(&loader, &playlist, decode_target);
+
+        // The image we're waiting on to finish decoding, if any.
+        let mut pending: Option<PathBuf> = None;
+
+        // The current image, if it's being decoded again with other
+        // options, to be swapped into the view without resetting it.
+        let mut redecoding: Option<PathBuf> = None;
+
+        // Files dropped onto the window; winit sends these one
+        // at a time, so they're collected until the batch is done.
+        let mut dropped: Vec<PathBuf> = vec![];
+
+        let mut input = InputState::default();
+
+        // The thumbnail grid, if it's open.
+        let mut gallery: Option<Gallery> = None;
+
+        let mut slideshow: Option<Slideshow> = None;
+        if config.slideshow {
+            slideshow = Some(start_slideshow(&mut playlist, &image_view, &config));
+        }
 
         event_loop.run(move |event, target| {
+            let is_idle = matches!(event, Event::AboutToWait);
             match event {
-                // Go to the next frame in a sequence.
-                Event::UserEvent(RequestNextFrame) => {
-                    if !image_view.advance() {
+                Event::UserEvent(UserEvent::ImageLoaded(path)) => {
+                    if pending.as_ref() == Some(&path)
+                        && let Some(loaded) = loader.get(&path, decode_target)
+                    {
+                        pending = None;
+                        let label = image_label(&playlist, decode_target);
+                        show_image(&mut image_view, loaded, &path, label, &config);
+                        if let Some(gallery) = &mut gallery {
+                            gallery.mark_dirty();
+                        }
+                        if let Some(slideshow) = &mut slideshow {
+                            slideshow.start(image_view.is_animated());
+                        }
+                    } else if redecoding.as_ref() == Some(&path)
+                        && let Some(loaded) = loader.get(&path, decode_target)
+                    {
+                        redecoding = None;
+                        image_view.set_label(image_label(&playlist, decode_target));
+                        image_view.redecode(loaded, &path, config.repeat);
+                    }
+                }
+                Event::UserEvent(UserEvent::TilesLoaded) => image_view.show_loaded_tiles(),
+                Event::UserEvent(UserEvent::FrameDecoded) => {
+                    if !image_view.show_current_frame() {
                         target.exit();
                     }
                 }
+                Event::UserEvent(UserEvent::ThumbLoaded(path)) => {
+                    if let Some(gallery) = &mut gallery
+                        && playlist.paths()[gallery.visible()].contains(&path)
+                    {
+                        gallery.mark_dirty();
+                    }
+                }
                 Event::WindowEvent {
                     event: WindowEvent::RedrawRequested,
                     ..
@@ -108,10 +354,47 @@ pub fn run<P: AsRef<Path>>(
                     ..
                 } => {
                     image_view.resize(size.width, size.height, true).unwrap();
+                    if let Some(gallery) = &mut gallery {
+                        gallery.resize((size.width, size.height));
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::DroppedFile(path),
+                    ..
+                } => dropped.push(path),
+
+                // Open the dropped files once they've all arrived,
+                // replacing the current list, or appending to it
+                // if shift is held.
+                Event::AboutToWait if !dropped.is_empty() => {
+                    let paths = expand_paths(&std::mem::take(&mut dropped), config.recursive);
+                    if !paths.is_empty() {
+                        if input.modifiers.shift_key() {
+                            let len = playlist.len();
+                            playlist.extend(paths);
+                            playlist.jump(len);
+                        } else {
+                            playlist.replace(paths);
+                        }
+                    }
                 }
 
                 _ => {
-                    if let Some(action) = handle_event(event) {
+                    // The gallery takes over navigation while it's open.
+                    let action = handle_event(event, &mut input, &config).and_then(|action| {
+                        match &mut gallery {
+                            Some(gallery) => gallery.handle(action),
+                            None => Some(action),
+                        }
+                    });
+                    if let Some(action) = action {
+                        // Moving through the images by hand
+                        // pauses the slideshow.
+                        if action.is_navigation()
+                            && let Some(slideshow) = &mut slideshow
+                        {
+                            slideshow.pause();
+                        }
                         match action {
                             Action::ToggleFullscreen => toggle_fullscreen(&window),
                             Action::ZoomIn => image_view.zoom_in(),
@@ -120,62 +403,334 @@ pub fn run<P: AsRef<Path>>(
                             Action::PanDown => image_view.pan_down(),
                             Action::PanRight => image_view.pan_right(),
                             Action::PanLeft => image_view.pan_left(),
+                            Action::ZoomAt(factor, anchor) => image_view.zoom_at(factor, anchor),
+                            Action::Scroll(steps) => image_view
+                                .zoom_at(WHEEL_ZOOM_STEP.powf(steps as f32), input.position),
+                            Action::Click(_) => {}
+                            Action::PanBy(dx, dy) => image_view.pan_by(dx, dy),
+                            Action::ToggleFit => image_view.toggle_fit(),
+                            Action::CycleFilter => image_view.cycle_filter(),
+                            Action::ToggleColorManagement => {
+                                decode_target.options.color_manage =
+                                    !decode_target.options.color_manage;
+                                if pending.is_none()
+                                    && let Some(image_path) = playlist.current()
+                                {
+                                    match loader.get(image_path, decode_target) {
+                                        Some(loaded) => {
+                                            image_view
+                                                .set_label(image_label(&playlist, decode_target));
+                                            image_view.redecode(loaded, image_path, config.repeat);
+                                        }
+                                        None => {
+                                            redecoding = Some(image_path.to_path_buf());
+                                            loader.request(image_path, decode_target);
+                                        }
+                                    }
+                                    prefetch(&loader, &playlist, decode_target);
+                                } else {
+                                    playlist.reload();
+                                }
+                            }
+                            Action::Rotate(clockwise) => image_view.rotate(clockwise),
+                            Action::Flip(horizontal) => image_view.flip(horizontal),
                             Action::ToggleInfo => image_view.toggle_label(),
+                            Action::TogglePause => image_view.control(Animator::toggle_pause),
+                            Action::StepFrame(forward) => {
+                                image_view.control(|animator| animator.step(forward))
+                            }
+                            Action::ChangeSpeed(faster) => {
+                                image_view.control(|animator| animator.change_speed(faster))
+                            }
+                            Action::SeekFrame(last) => image_view.control(|animator| {
+                                animator.seek(if last { usize::MAX } else { 0 })
+                            }),
                             Action::ChangeImage(next) => {
-                                index = if next {
-                                    if index >= image_paths.len() - 1 {
-                                        0
-                                    } else {
-                                        index + 1
-                                    }
+                                if next {
+                                    playlist.next();
                                 } else {
-                                    if index == 0 {
-                                        image_paths.len() - 1
-                                    } else {
-                                        index.saturating_sub(1)
-                                    }
+                                    playlist.prev();
+                                }
+                            }
+                            Action::FirstImage => playlist.jump(0),
+                            Action::LastImage => playlist.jump(playlist.len() - 1),
+                            Action::SkipImages(count) => {
+                                let last = playlist.len() as isize - 1;
+                                let index = (playlist.index() as isize + count).clamp(0, last);
+                                playlist.jump(index as usize);
+                            }
+                            Action::GoToPrompt => {
+                                let prompt = Prompt::GoTo(String::new());
+                                image_view.set_prompt(Some(prompt.display()));
+                                input.prompt = Some(prompt);
+                            }
+                            Action::SearchPrompt => {
+                                let prompt = Prompt::Search {
+                                    query: String::new(),
+                                    origin: playlist.index(),
                                 };
-                                if let Some(image_path) = image_paths.get(index) {
-                                    let label = format!(
-                                        "{} {}/{}",
-                                        image_path.as_ref().display(),
-                                        index + 1,
-                                        image_paths.len()
-                                    );
-
-                                    // Because by this point the WM has already positioned
-                                    // the window, it's better to resize the image to the window
-                                    // rather than vice-versa, because otherwise the window
-                                    // positioning could get messed up.
-                                    let view = Viewer::new(
-                                        image_path.as_ref(),
-                                        &window,
-                                        &proxy,
-                                        ViewOpts {
-                                            max_side,
-                                            resize_window: false,
-                                            show_label: image_view.is_label_visible(),
-                                            label,
-                                        },
-                                    );
-                                    match view {
-                                        Ok(view) => image_view = view,
-                                        Err(err) => eprintln!("Error loading image: {err}"),
+                                image_view.set_prompt(Some(prompt.display()));
+                                input.prompt = Some(prompt);
+                            }
+                            Action::PromptChanged => {
+                                if let Some(prompt) = &input.prompt {
+                                    if let Prompt::Search { query, origin } = prompt
+                                        && let Some(index) = playlist.find(query, *origin)
+                                    {
+                                        playlist.jump(index);
                                     }
-                                } else {
-                                    eprintln!("No image found for index {index}")
+                                    image_view.set_prompt(Some(prompt.display()));
+                                }
+                            }
+                            Action::ClosePrompt(accept) => {
+                                match input.prompt.take() {
+                                    Some(Prompt::GoTo(number)) if accept => {
+                                        // Image numbers start at 1.
+                                        if let Ok(number) = number.parse::<usize>()
+                                            && number > 0
+                                        {
+                                            playlist.jump(number - 1);
+                                        }
+                                    }
+                                    Some(Prompt::Search { origin, .. }) if !accept => {
+                                        playlist.jump(origin);
+                                    }
+                                    _ => {}
+                                }
+                                image_view.set_prompt(None);
+                            }
+                            Action::ToggleGallery if gallery.is_none() => {
+                                let size = window.inner_size();
+                                gallery = Some(Gallery::new(
+                                    playlist.index(),
+                                    playlist.len(),
+                                    (size.width, size.height),
+                                ));
+                            }
+                            Action::ToggleGallery | Action::OpenSelected | Action::OpenImage(_) => {
+                                if let Action::OpenImage(index) = action {
+                                    playlist.jump(index);
                                 }
+                                gallery = None;
+                                image_view.hide_grid();
+                                image_view.set_label(image_label(&playlist, decode_target));
                             }
-                            Action::Quit => target.exit(),
+                            // Once started, a slideshow keeps its order
+                            // (and place in it) when paused and resumed.
+                            Action::ToggleSlideshow => match &mut slideshow {
+                                Some(slideshow) if slideshow.is_paused() => {
+                                    slideshow.resume(image_view.is_animated())
+                                }
+                                Some(slideshow) => slideshow.pause(),
+                                None => {
+                                    slideshow =
+                                        Some(start_slideshow(&mut playlist, &image_view, &config))
+                                }
+                            },
+                            Action::Quit | Action::Close => target.exit(),
                         }
                     }
                 }
             }
+
+            // Move an animation and the slideshow along once the events
+            // are handled, skipping any frames that ended while we were busy.
+            if is_idle {
+                let now = Instant::now();
+                let tick = image_view.tick(now);
+                if tick.is_changed && !image_view.show_current_frame() {
+                    target.exit();
+                }
+                if let Some(slideshow) = &mut slideshow {
+                    let is_timed_out = slideshow.tick(now);
+                    let is_played = tick.is_played_through && slideshow.played();
+                    if is_timed_out || is_played {
+                        playlist.next();
+                    }
+                }
+            }
+
+            let changes = playlist.take_changes();
+            if changes.contains(&Change::Current)
+                && let Some(image_path) = playlist.current()
+            {
+                decode_target.bounds = view::max_bounds(&window, max_side);
+                redecoding = None;
+
+                // Show the image right away if it's been prefetched,
+                // otherwise wait for it to finish decoding.
+                if let Some(loaded) = loader.get(image_path, decode_target) {
+                    pending = None;
+                    show_image(
+                        &mut image_view,
+                        loaded,
+                        image_path,
+                        image_label(&playlist, decode_target),
+                        &config,
+                    );
+                    if let Some(slideshow) = &mut slideshow {
+                        slideshow.start(image_view.is_animated());
+                    }
+                } else {
+                    pending = Some(image_path.to_path_buf());
+                    loader.request(image_path, decode_target);
+                }
+                prefetch(&loader, &playlist, decode_target);
+            } else if changes.contains(&Change::List) {
+                // The image's position in the list may have changed.
+                image_view.set_label(image_label(&playlist, decode_target));
+                prefetch(&loader, &playlist, decode_target);
+            }
+            if !changes.is_empty()
+                && let Some(gallery) = &mut gallery
+            {
+                gallery.set_len(playlist.len());
+            }
+
+            // Once zoomed in past the detail in a scaled-down image,
+            // decode it at full resolution and show that instead.
+            if pending.is_none()
+                && image_view.wants_full_res()
+                && let Some(image_path) = playlist.current()
+            {
+                let full_target = Target {
+                    bounds: image_view.max_image_size(),
+                    full_res: true,
+                    ..decode_target
+                };
+                match loader.take_full_res(image_path, full_target) {
+                    Some(loaded) => image_view.show_full_res(loaded),
+                    None => loader.request(image_path, full_target),
+                }
+            }
+
+            // Redraw the gallery once the pending events are handled,
+            // as many thumbnails may have finished in the meantime.
+            if is_idle
+                && let Some(gallery) = &mut gallery
+                && gallery.take_dirty()
+            {
+                let paths: Vec<&Path> = playlist.paths()[gallery.visible()]
+                    .iter()
+                    .map(PathBuf::as_path)
+                    .collect();
+                thumbnailer.request(&paths);
+
+                let label = entry_label(&playlist, gallery.selected());
+                let (data, size) = gallery.render(playlist.paths(), &thumbnailer);
+                image_view.set_label(label);
+                if let Err(err) = image_view.show_grid(&data, size) {
+                    eprintln!("Error showing gallery: {err}");
+                }
+            }
+
+            // Sleep until the next frame or image is due, if any.
+            if is_idle {
+                let slideshow_deadline = slideshow.as_ref().and_then(Slideshow::deadline);
+                let deadline = image_view
+                    .deadline()
+                    .into_iter()
+                    .chain(slideshow_deadline)
+                    .min();
+                target.set_control_flow(match deadline {
+                    Some(deadline) => ControlFlow::WaitUntil(deadline),
+                    None => ControlFlow::Wait,
+                });
+            }
         })?;
     }
     Ok(())
 }
 
+/// The label for the current image, e.g. `foo.png 3/10`.
+fn image_label(playlist: &Playlist, target: Target) -> String {
+    let mut label = entry_label(playlist, playlist.index());
+    if !target.options.color_manage {
+        label.push_str(" (unmanaged colors)");
+    }
+    label
+}
+
+/// The label for the image at the given position.
+fn entry_label(playlist: &Playlist, index: usize) -> String {
+    let path = playlist
+        .paths()
+        .get(index)
+        .map_or(Path::new(""), PathBuf::as_path);
+    format!("{} {}/{}", path.display(), index + 1, playlist.len())
+}
+
+/// Create the view for a loaded image, or, if it couldn't be
+/// loaded, a placeholder showing the path and the reason why.
+fn load_viewer(
+    loaded: Loaded,
+    path: &Path,
+    window: &Window,
+    proxy: &EventLoopProxy<UserEvent>,
+    opts: ViewOpts,
+    repeat: Option<Repeat>,
+) -> anyhow::Result<Viewer> {
+    let err = match loaded {
+        Ok(image) => match Viewer::new(image, window, proxy, opts.clone(), repeat) {
+            Ok(view) => return Ok(view),
+            Err(err) => err,
+        },
+        Err(err) => err.into(),
+    };
+    eprintln!("Error loading image {}: {err}", path.display());
+    let mut view = Viewer::new(Arc::new(Image::placeholder()), window, proxy, opts, None)?;
+    view.set_message(Some(format!("{}\n{err}", path.display())));
+    Ok(view)
+}
+
+/// Show a newly-loaded image in place of the current one.
+///
+/// Because by this point the WM has already positioned the
+/// window, the image is fit to the window rather than vice-versa,
+/// as otherwise the window positioning could get messed up.
+fn show_image(
+    image_view: &mut Viewer,
+    loaded: Loaded,
+    path: &Path,
+    label: String,
+    config: &Config,
+) {
+    image_view.set_label(label);
+    image_view.show(loaded, path, config.repeat);
+}
+
+/// Start a slideshow from the current image,
+/// shuffling the rest if configured to.
+fn start_slideshow(playlist: &mut Playlist, image_view: &Viewer, config: &Config) -> Slideshow {
+    if config.shuffle {
+        playlist.shuffle();
+    }
+    let mut slideshow = Slideshow::new(config.slideshow_interval);
+    slideshow.start(image_view.is_animated());
+    slideshow
+}
+
+/// Start decoding the images around the current one,
+/// nearest first, so they're ready when navigating.
+fn prefetch(loader: &Loader, playlist: &Playlist, target: Target) {
+    let (paths, index) = (playlist.paths(), playlist.index());
+    let Some(current) = playlist.current() else {
+        return;
+    };
+    let len = paths.len();
+    let mut neighbors: Vec<&Path> = vec![];
+    for offset in 1..=PREFETCH_COUNT.min(len / 2) {
+        for i in [(index + offset) % len, (index + len - offset) % len] {
+            let path = paths[i].as_path();
+            if i != index && !neighbors.contains(&path) {
+                neighbors.push(path);
+            }
+        }
+    }
+    loader.prefetch(current, &neighbors, target);
+}
+
+#[derive(Clone, Copy)]
 enum Action {
     ToggleFullscreen,
     ZoomIn,
@@ -187,9 +742,187 @@ enum Action {
     ToggleInfo,
     ChangeImage(bool),
     Quit,
+
+    /// Close the gallery, or quit if it isn't open.
+    Close,
+
+    /// Zoom by a factor, keeping the point
+    /// under the cursor in place.
+    ZoomAt(f32, (f64, f64)),
+
+    /// Pan by an exact number of pixels.
+    PanBy(i32, i32),
+
+    /// Toggle between fit-to-window and 1:1 zoom.
+    ToggleFit,
+
+    /// Switch to the next resampling filter.
+    CycleFilter,
+
+    /// Switch between converting colors from images'
+    /// ICC profiles and showing them as-is.
+    ToggleColorManagement,
+
+    /// Rotate a quarter turn clockwise (`true`) or counter-clockwise.
+    Rotate(bool),
+
+    /// Mirror horizontally (`true`) or vertically.
+    Flip(bool),
+
+    /// Pause or resume an animation.
+    TogglePause,
+
+    /// Pause and step one frame forward (`true`) or back.
+    StepFrame(bool),
+
+    /// Play faster (`true`) or slower.
+    ChangeSpeed(bool),
+
+    /// Jump to the last (`true`) or first frame.
+    SeekFrame(bool),
+
+    /// Go to the first or last image.
+    FirstImage,
+    LastImage,
+
+    /// Move through the list by a number of images,
+    /// stopping at either end.
+    SkipImages(isize),
+
+    /// Start typing an image number to go to.
+    GoToPrompt,
+
+    /// Start typing part of a file name to search for.
+    SearchPrompt,
+
+    /// The prompt's text was edited.
+    PromptChanged,
+
+    /// Accept (`true`) or cancel the prompt.
+    ClosePrompt(bool),
+
+    /// Scroll the mouse wheel by a number of steps (up if positive);
+    /// this zooms the image, or scrolls the gallery.
+    Scroll(f64),
+
+    /// Click at a point, e.g. to select a thumbnail.
+    Click((f64, f64)),
+
+    /// Show or hide the thumbnail gallery.
+    ToggleGallery,
+
+    /// Open the image selected in the gallery.
+    OpenSelected,
+
+    /// Open the image at the given position from the gallery.
+    OpenImage(usize),
+
+    /// Start the slideshow, or pause or resume it.
+    ToggleSlideshow,
+}
+impl Action {
+    /// Whether the action moves to another image,
+    /// or starts to (e.g. opening a prompt).
+    fn is_navigation(self) -> bool {
+        matches!(
+            self,
+            Action::ChangeImage(_)
+                | Action::FirstImage
+                | Action::LastImage
+                | Action::SkipImages(_)
+                | Action::GoToPrompt
+                | Action::SearchPrompt
+                | Action::ToggleGallery
+                | Action::OpenImage(_)
+        )
+    }
+}
+
+/// How many images [`Action::SkipImages`] moves by
+/// for the default page up/down bindings.
+const SKIP_COUNT: isize = 10;
+
+/// A text prompt being typed into.
+enum Prompt {
+    /// An image number to go to, e.g. `:123`.
+    GoTo(String),
+
+    /// Part of a file name, e.g. `/foo`, searched for incrementally
+    /// from the image that was current when the search started.
+    Search { query: String, origin: usize },
+}
+impl Prompt {
+    /// The prompt as shown in the view.
+    fn display(&self) -> String {
+        match self {
+            Prompt::GoTo(number) => format!(":{number}"),
+            Prompt::Search { query, .. } => format!("/{query}"),
+        }
+    }
+
+    /// Apply a key press to the prompt.
+    fn edit(&mut self, key: KeyCode, text: Option<&str>) -> Option<Action> {
+        let input = match self {
+            Prompt::GoTo(number) => number,
+            Prompt::Search { query, .. } => query,
+        };
+        match key {
+            KeyCode::Enter | KeyCode::NumpadEnter => Some(Action::ClosePrompt(true)),
+            KeyCode::Escape => Some(Action::ClosePrompt(false)),
+            KeyCode::Backspace => input.pop().map(|_| Action::PromptChanged),
+            _ => {
+                let is_number = matches!(self, Prompt::GoTo(_));
+                let text: String = text?
+                    .chars()
+                    .filter(|c| !c.is_control() && (!is_number || c.is_ascii_digit()))
+                    .collect();
+                if text.is_empty() {
+                    return None;
+                }
+                match self {
+                    Prompt::GoTo(number) => number.push_str(&text),
+                    Prompt::Search { query, .. } => query.push_str(&text),
+                }
+                Some(Action::PromptChanged)
+            }
+        }
+    }
+}
+
+/// How much a single scroll wheel step zooms by.
+const WHEEL_ZOOM_STEP: f32 = 1.1;
+
+/// Pixel-based scroll deltas (e.g. from touchpads)
+/// that correspond to a single wheel step.
+const PIXELS_PER_WHEEL_STEP: f64 = 50.;
+
+/// Max interval between clicks to count as a double-click.
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Keyboard and mouse state needed to interpret input events.
+#[derive(Default)]
+struct InputState {
+    modifiers: ModifiersState,
+
+    /// Cursor position.
+    position: (f64, f64),
+
+    /// Where the cursor was when the drag last moved the image,
+    /// if the left button is held down.
+    drag_from: Option<(f64, f64)>,
+
+    last_click: Option<Instant>,
+
+    /// The prompt being typed into, if any;
+    /// key bindings are ignored while it's open.
+    prompt: Option<Prompt>,
 }
 
-fn handle_event(event: Event<RequestNextFrame>) -> Option<Action> {
+fn handle_event(
+    event: Event<UserEvent>,
+    input: &mut InputState,
+    config: &Config,
+) -> Option<Action> {
     match event {
         Event::WindowEvent {
             event:
@@ -198,29 +931,86 @@ fn handle_event(event: Event<RequestNextFrame>) -> Option<Action> {
                         KeyEvent {
                             state: ElementState::Pressed,
                             physical_key: PhysicalKey::Code(key),
+                            text,
                             ..
                         },
                     ..
                 },
             ..
-        } => match key {
-            KeyCode::KeyF => Some(Action::ToggleFullscreen),
-            KeyCode::KeyE => Some(Action::ZoomIn),
-            KeyCode::KeyH => Some(Action::ZoomOut),
-            KeyCode::ArrowUp => Some(Action::PanUp),
-            KeyCode::ArrowDown => Some(Action::PanDown),
-            KeyCode::ArrowRight => Some(Action::PanRight),
-            KeyCode::ArrowLeft => Some(Action::PanLeft),
-            KeyCode::Quote => Some(Action::ChangeImage(true)),
-            KeyCode::Comma => Some(Action::ChangeImage(false)),
-            KeyCode::KeyI => Some(Action::ToggleInfo),
-            KeyCode::Escape | KeyCode::KeyQ => Some(Action::Quit),
-            _ => None,
+        } => match &mut input.prompt {
+            Some(prompt) => prompt.edit(key, text.as_deref()),
+            None => config.bindings.get(&(input.modifiers, key)).copied(),
         },
+        Event::WindowEvent {
+            event: WindowEvent::ModifiersChanged(modifiers),
+            ..
+        } => {
+            input.modifiers = modifiers.state();
+            None
+        }
         Event::WindowEvent {
             event: WindowEvent::CloseRequested,
             ..
         } => Some(Action::Quit),
+        Event::WindowEvent {
+            event: WindowEvent::MouseWheel { delta, .. },
+            ..
+        } => {
+            let steps = match delta {
+                MouseScrollDelta::LineDelta(_, y) => y as f64,
+                MouseScrollDelta::PixelDelta(pos) => pos.y / PIXELS_PER_WHEEL_STEP,
+            };
+            Some(Action::Scroll(steps))
+        }
+        Event::WindowEvent {
+            event: WindowEvent::TouchpadMagnify { delta, .. },
+            ..
+        } => Some(Action::ZoomAt(1. + delta as f32, input.position)),
+        Event::WindowEvent {
+            event: WindowEvent::CursorMoved { position, .. },
+            ..
+        } => {
+            input.position = (position.x, position.y);
+            let (from_x, from_y) = input.drag_from?;
+
+            // Only move by whole pixels, carrying over the
+            // remainder so the image tracks the cursor exactly.
+            let dx = (position.x - from_x).trunc();
+            let dy = (position.y - from_y).trunc();
+            if dx == 0. && dy == 0. {
+                return None;
+            }
+            input.drag_from = Some((from_x + dx, from_y + dy));
+            Some(Action::PanBy(-dx as i32, -dy as i32))
+        }
+        Event::WindowEvent {
+            event:
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Left,
+                    ..
+                },
+            ..
+        } => match state {
+            ElementState::Pressed => {
+                input.drag_from = Some(input.position);
+                let now = Instant::now();
+                let is_double = input
+                    .last_click
+                    .is_some_and(|last| now - last < DOUBLE_CLICK_INTERVAL);
+                if is_double {
+                    input.last_click = None;
+                    Some(Action::ToggleFit)
+                } else {
+                    input.last_click = Some(now);
+                    Some(Action::Click(input.position))
+                }
+            }
+            ElementState::Released => {
+                input.drag_from = None;
+                None
+            }
+        },
         _ => None,
     }
 }
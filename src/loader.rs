@@ -0,0 +1,185 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::{
+    UserEvent,
+    cache::Cache,
+    img::{self, DecodeOptions, Image},
+    pool::{Pool, Work},
+};
+
+/// The result of decoding an image, shared between
+/// the cache and the view showing it.
+pub type Loaded = Result<Arc<Image>, img::Error>;
+
+/// What an image is decoded for: the bounds
+/// it's fit within, and how it's decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target {
+    pub bounds: (u32, u32),
+    pub options: DecodeOptions,
+
+    /// Keep the image's full resolution, tiling it if
+    /// it's larger than the bounds (see [`img::read_full_image`]).
+    pub full_res: bool,
+}
+
+/// Images are decoded for a particular path and target.
+type Key = (PathBuf, Target);
+
+/// Decodes images on a pool of worker threads,
+/// keeping the results in a memory-capped LRU cache.
+///
+/// A [`UserEvent::ImageLoaded`] is emitted whenever
+/// a decode finishes.
+pub struct Loader {
+    pool: Pool<State>,
+}
+
+struct State {
+    /// Images that were explicitly requested for display,
+    /// as opposed to prefetched.
+    requested: HashSet<Key>,
+
+    /// Images we want to keep around, i.e. the current
+    /// image and its neighbors. These are never evicted
+    /// to make room for prefetched images.
+    wanted: HashSet<Key>,
+
+    /// Decoded images, capped by the total size of their pixel data.
+    cache: Cache<Key, Loaded>,
+
+    /// The last full-resolution image decoded, until it's taken.
+    /// These can be far larger than the whole cache, so they're
+    /// handed straight to the view instead of being cached.
+    full_res: Option<(Key, Loaded)>,
+}
+
+impl Loader {
+    pub fn new(proxy: EventLoopProxy<UserEvent>, workers: usize, budget: usize) -> Self {
+        let state = State {
+            requested: HashSet::new(),
+            wanted: HashSet::new(),
+            cache: Cache::new(budget),
+            full_res: None,
+        };
+        Self {
+            pool: Pool::new(state, proxy, workers),
+        }
+    }
+
+    /// Get a decoded image if it's ready.
+    pub fn get(&self, path: &Path, target: Target) -> Option<Loaded> {
+        self.pool
+            .with(|jobs| jobs.state.cache.get(&(path.to_path_buf(), target)))
+    }
+
+    /// Take a full-resolution image if it's ready.
+    pub fn take_full_res(&self, path: &Path, target: Target) -> Option<Loaded> {
+        let key = (path.to_path_buf(), target);
+        let (_, loaded) = self
+            .pool
+            .with(|jobs| jobs.state.full_res.take_if(|(done, _)| *done == key))?;
+        Some(loaded)
+    }
+
+    /// Add an already-decoded image to the cache.
+    pub fn insert(&self, path: &Path, target: Target, loaded: Loaded) {
+        let key = (path.to_path_buf(), target);
+        self.pool.with(|jobs| {
+            jobs.state.wanted.insert(key.clone());
+            jobs.state.insert(key, loaded, true);
+        });
+    }
+
+    /// Decode an image as soon as possible, ahead of any prefetching.
+    pub fn request(&self, path: &Path, target: Target) {
+        let key = (path.to_path_buf(), target);
+        self.pool.with(|jobs| {
+            jobs.state.wanted.insert(key.clone());
+            if jobs.state.cache.contains(&key) {
+                return;
+            }
+            jobs.state.requested.insert(key.clone());
+            if jobs.in_flight.contains(&key) {
+                return;
+            }
+            jobs.queue.retain(|queued| *queued != key);
+            jobs.queue.push_front(key);
+        });
+    }
+
+    /// Decode the given images in the background, in order.
+    ///
+    /// This replaces any previously prefetched images
+    /// that haven't been started yet.
+    pub fn prefetch(&self, current: &Path, paths: &[&Path], target: Target) {
+        let current = (current.to_path_buf(), target);
+        let keys: Vec<Key> = paths
+            .iter()
+            .map(|path| (path.to_path_buf(), target))
+            .collect();
+
+        self.pool.with(|jobs| {
+            jobs.state.wanted = keys.iter().cloned().collect();
+            jobs.state.wanted.insert(current.clone());
+
+            // Drop a full-resolution image that's no longer wanted.
+            jobs.state.full_res.take_if(|(done, _)| done.0 != current.0);
+
+            // Keep the current image if it's still queued,
+            // but drop any stale prefetches.
+            jobs.queue.retain(|queued| *queued == current);
+            for key in keys {
+                if !jobs.state.cache.contains(&key) && !jobs.is_pending(&key) {
+                    jobs.queue.push_back(key);
+                }
+            }
+        });
+    }
+}
+
+impl State {
+    fn insert(&mut self, key: Key, loaded: Loaded, is_requested: bool) {
+        let size = loaded.as_ref().map_or(0, |image| image.byte_size());
+
+        // Prefetched images shouldn't push out the
+        // images we actually want to show.
+        let protected = if is_requested {
+            HashSet::new()
+        } else {
+            self.wanted.clone()
+        };
+        if self.cache.make_room(size, &protected) {
+            self.cache.insert(key, loaded, size);
+        }
+    }
+}
+impl Work for State {
+    type Job = Key;
+    type Output = Loaded;
+
+    fn run((path, target): &Key) -> Loaded {
+        if target.full_res {
+            img::read_full_image(path, target.bounds, target.options)
+        } else {
+            img::read_image(path, target.bounds, target.options)
+        }
+        .map(Arc::new)
+    }
+
+    fn finish(&mut self, key: Key, loaded: Loaded) -> UserEvent {
+        let is_requested = self.requested.remove(&key);
+        if key.1.full_res {
+            self.full_res = Some((key.clone(), loaded));
+        } else {
+            self.insert(key.clone(), loaded, is_requested);
+        }
+        UserEvent::ImageLoaded(key.0)
+    }
+}
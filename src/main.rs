@@ -1,5 +1,5 @@
 use bpaf::Bpaf;
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 #[derive(Debug, Bpaf)]
 #[bpaf(options, version)]
@@ -12,11 +12,91 @@ struct Args {
     #[bpaf(short, long)]
     max_side: Option<u32>,
 
+    /// Include images in subdirectories
+    /// when a directory is given
+    #[bpaf(short, long)]
+    recursive: bool,
+
+    /// Config file to use instead of
+    /// $XDG_CONFIG_HOME/vu/config.toml
+    #[bpaf(short, long, argument("PATH"))]
+    config: Option<PathBuf>,
+
+    /// Resampling filter used when zooming:
+    /// nearest, bilinear, catmull-rom or lanczos
+    #[bpaf(long, argument("FILTER"))]
+    filter: Option<vu::Filter>,
+
+    /// How many times animations play: `forever` or a
+    /// number, overriding the images' own loop counts
+    #[bpaf(long("loop"), argument("COUNT"))]
+    repeat: Option<vu::Repeat>,
+
+    /// Start a slideshow, showing each image for this many
+    /// seconds (animations play through at least once)
+    #[bpaf(
+        long,
+        argument("SECS"),
+        guard(is_interval, "must be a positive number of seconds"),
+        optional
+    )]
+    slideshow: Option<f64>,
+
+    /// Shuffle the images when a slideshow starts
+    #[bpaf(long)]
+    shuffle: bool,
+
+    /// Ignore images' EXIF orientation, showing
+    /// them as stored (e.g. sideways)
+    #[bpaf(long)]
+    no_auto_orient: bool,
+
+    /// ICC profile (e.g. the display's) to
+    /// convert colors to, instead of sRGB
+    #[bpaf(long, argument("PATH"))]
+    output_profile: Option<PathBuf>,
+
+    /// Show colors as stored, ignoring
+    /// images' embedded ICC profiles
+    #[bpaf(long)]
+    unmanaged: bool,
+
+    /// Memory (in MiB) an animation's frames may take before
+    /// they're decoded as they play instead of all up front
+    #[bpaf(long, argument("MIB"))]
+    animation_memory: Option<usize>,
+
+    /// Images, directories, glob patterns or file:// URIs;
+    /// `-` reads an image from stdin
     #[bpaf(positional("PATHS"))]
     paths: Vec<PathBuf>,
 }
 
 fn main() -> anyhow::Result<()> {
     let opts = args().run();
-    vu::run(&opts.title, &opts.paths, opts.max_side)
+    let mut config = vu::Config::load(opts.config.as_deref())?;
+    if let Some(filter) = opts.filter {
+        config.filter = filter;
+    }
+    if let Some(repeat) = opts.repeat {
+        config.repeat = Some(repeat);
+    }
+    config.recursive = opts.recursive;
+    if let Some(secs) = opts.slideshow {
+        config.slideshow_interval = Duration::from_secs_f64(secs);
+        config.slideshow = true;
+    }
+    config.shuffle = opts.shuffle;
+    config.auto_orient = !opts.no_auto_orient;
+    config.color_manage = !opts.unmanaged;
+    config.output_profile = opts.output_profile;
+    if let Some(mib) = opts.animation_memory {
+        config.frame_budget = mib.saturating_mul(1024 * 1024);
+    }
+    let paths = vu::expand_paths(&opts.paths, config.recursive);
+    vu::run(&opts.title, vu::Playlist::new(paths), opts.max_side, config)
+}
+
+fn is_interval(secs: &f64) -> bool {
+    secs.is_finite() && *secs > 0.
 }
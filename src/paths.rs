@@ -0,0 +1,226 @@
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use url::Url;
+
+use crate::img::{is_stdin, is_supported};
+
+/// Expand the given paths into a list of image files.
+///
+/// - Files are kept in the order given, skipping any
+///   that aren't a supported image format (going by
+///   their contents, not their extension).
+/// - Directories are expanded into the supported image files
+///   they contain (descending into subdirectories if `recursive`),
+///   in natural sort order.
+/// - Paths that don't exist but look like glob patterns
+///   (e.g. a quoted `'renders/*.png'`) are expanded as globs.
+/// - `file://` URIs (as passed by file managers) are
+///   converted to paths, and `-` (stdin) is kept as-is.
+pub fn expand_paths<P: AsRef<Path>>(paths: &[P], recursive: bool) -> Vec<PathBuf> {
+    let mut expanded = vec![];
+    for path in paths {
+        let path = path.as_ref();
+        let path = &file_uri_path(path).unwrap_or_else(|| path.to_path_buf());
+        if is_stdin(path) {
+            expanded.push(path.to_path_buf());
+        } else if path.is_dir() {
+            let mut files = vec![];
+            read_dir(path, recursive, &mut files);
+            files.sort_by(|a, b| path_cmp(a, b));
+            expanded.extend(files);
+        } else if !path.exists() && is_glob(path) {
+            let pattern = path.to_string_lossy();
+            match glob::glob(&pattern) {
+                Ok(entries) => {
+                    let mut files: Vec<_> = entries
+                        .filter_map(Result::ok)
+                        .filter(|path| path.is_file() && is_supported(path))
+                        .collect();
+                    files.sort_by(|a, b| path_cmp(a, b));
+                    expanded.extend(files);
+                }
+                Err(err) => eprintln!("Invalid pattern {pattern}: {err}"),
+            }
+        } else if !path.exists() {
+            eprintln!("No such file: {}", path.display());
+        } else if is_supported(path) {
+            expanded.push(path.to_path_buf());
+        } else {
+            eprintln!("Skipping unsupported file: {}", path.display());
+        }
+    }
+    expanded
+}
+
+/// Collect the supported image files in a directory.
+fn read_dir(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Failed to read directory {}: {err}", dir.display());
+            return;
+        }
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            if recursive {
+                read_dir(&path, recursive, files);
+            }
+        } else if is_supported(&path) {
+            files.push(path);
+        }
+    }
+}
+
+/// The local path of a `file://` URI.
+fn file_uri_path(path: &Path) -> Option<PathBuf> {
+    let uri = path.to_str()?;
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Compare paths by their directories and then their file names,
+/// each in natural order, so that the files in a directory come
+/// before those in its subdirectories.
+fn path_cmp(a: &Path, b: &Path) -> Ordering {
+    let dirs = |path: &Path| {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        parent
+            .components()
+            .map(|dir| dir.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+    let (a_dirs, b_dirs) = (dirs(a), dirs(b));
+    for (x, y) in a_dirs.iter().zip(&b_dirs) {
+        let ord = natural_cmp(x, y);
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    let name = |path: &Path| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    a_dirs
+        .len()
+        .cmp(&b_dirs.len())
+        .then_with(|| natural_cmp(&name(a), &name(b)))
+}
+
+/// Compare strings ignoring case, and so that runs of digits
+/// are compared numerically, e.g. `img-2.png` sorts before `Img-10.png`.
+///
+/// Strings that only differ in case or in zero padding
+/// are ordered by the first such difference, so the
+/// order is stable.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    let mut tiebreak = Ordering::Equal;
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return tiebreak,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_digits(&mut a);
+                let y = take_digits(&mut b);
+
+                // Compare by magnitude, ignoring leading zeros.
+                let (xt, yt) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ord = xt.len().cmp(&yt.len()).then_with(|| xt.cmp(yt));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                tiebreak = tiebreak.then(x.len().cmp(&y.len()));
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.to_lowercase().cmp(y.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                tiebreak = tiebreak.then(x.cmp(&y));
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut paths: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+        paths.sort_by(|a, b| path_cmp(a, b));
+        paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn numbers_sort_by_magnitude() {
+        assert_eq!(
+            sorted(&["img-10.png", "img-2.png", "img-1.png"]),
+            ["img-1.png", "img-2.png", "img-10.png"]
+        );
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert_eq!(
+            sorted(&["Img-3.png", "img-1.png", "IMG-2.png"]),
+            ["img-1.png", "IMG-2.png", "Img-3.png"]
+        );
+    }
+
+    #[test]
+    fn zero_padding_only_breaks_ties() {
+        assert_eq!(natural_cmp("img-02", "img-2a"), Ordering::Less);
+        assert_eq!(natural_cmp("img-02b", "img-2a"), Ordering::Greater);
+        assert_eq!(natural_cmp("img-02", "img-2"), Ordering::Greater);
+        assert_eq!(natural_cmp("Img-2", "img-02"), Ordering::Less);
+        assert_eq!(natural_cmp("img-2", "img-2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn files_before_subdirectories() {
+        assert_eq!(
+            sorted(&[
+                "dir/b/img-1.png",
+                "dir/img-2.png",
+                "dir/A/img-3.png",
+                "dir/img-1.png"
+            ]),
+            [
+                "dir/img-1.png",
+                "dir/img-2.png",
+                "dir/A/img-3.png",
+                "dir/b/img-1.png"
+            ]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use std::path::{Path, PathBuf};
+
+/// A change to the playlist, for the viewer to react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// The current image is a different one.
+    Current,
+
+    /// Paths were added, removed or reordered,
+    /// e.g. so the image's position in the list changed.
+    List,
+}
+
+/// The list of images being viewed, and which one is current.
+///
+/// Changes are queued up and taken with [`Playlist::take_changes`],
+/// so that the viewer can update once however many were made.
+#[derive(Debug, Default)]
+pub struct Playlist {
+    paths: Vec<PathBuf>,
+    index: usize,
+    changes: Vec<Change>,
+}
+impl Playlist {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths,
+            index: 0,
+            changes: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The position of the current image.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.paths.get(self.index).map(PathBuf::as_path)
+    }
+
+    /// The position of the first path from `from` onwards (wrapping
+    /// around) whose file name contains `query`, ignoring case.
+    pub fn find(&self, query: &str, from: usize) -> Option<usize> {
+        let query = query.to_lowercase();
+        let len = self.len();
+        (0..len).map(|i| (from + i) % len).find(|&i| {
+            self.paths[i]
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().to_lowercase().contains(&query))
+        })
+    }
+
+    /// Go to the image at the given position,
+    /// if there is one.
+    pub fn jump(&mut self, index: usize) {
+        if index < self.len() && index != self.index {
+            self.index = index;
+            self.notify(Change::Current);
+        }
+    }
+
+    /// Treat the current image as changed,
+    /// e.g. so that it's decoded again.
+    pub fn reload(&mut self) {
+        if !self.is_empty() {
+            self.notify(Change::Current);
+        }
+    }
+
+    /// Go to the next image, wrapping around.
+    pub fn next(&mut self) {
+        if !self.is_empty() {
+            self.jump((self.index + 1) % self.len());
+        }
+    }
+
+    /// Go to the previous image, wrapping around.
+    pub fn prev(&mut self) {
+        if !self.is_empty() {
+            self.jump((self.index + self.len() - 1) % self.len());
+        }
+    }
+
+    /// Replace all the paths, going to the first one.
+    pub fn replace(&mut self, paths: Vec<PathBuf>) {
+        self.paths = paths;
+        self.index = 0;
+        self.notify(Change::List);
+        self.notify(Change::Current);
+    }
+
+    /// Insert paths at the given position (clamped to the end
+    /// of the list), keeping the current image current.
+    pub fn insert(&mut self, index: usize, paths: impl IntoIterator<Item = PathBuf>) {
+        let index = index.min(self.len());
+        let was_empty = self.is_empty();
+        let len = self.len();
+        self.paths.splice(index..index, paths);
+        let added = self.len() - len;
+        if added == 0 {
+            return;
+        }
+        if was_empty {
+            self.notify(Change::Current);
+        } else if index <= self.index {
+            self.index += added;
+        }
+        self.notify(Change::List);
+    }
+
+    /// Add paths to the end of the list.
+    pub fn extend(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.insert(self.len(), paths);
+    }
+
+    /// Remove the path at the given position. If it's the
+    /// current image, the one after it becomes current.
+    pub fn remove(&mut self, index: usize) -> Option<PathBuf> {
+        if index >= self.len() {
+            return None;
+        }
+        let path = self.paths.remove(index);
+        if index < self.index {
+            self.index -= 1;
+        } else if index == self.index {
+            if self.index >= self.len() {
+                self.index = 0;
+            }
+            self.notify(Change::Current);
+        }
+        self.notify(Change::List);
+        Some(path)
+    }
+
+    /// Move a path to another position,
+    /// keeping the current image current.
+    pub fn reorder(&mut self, from: usize, to: usize) {
+        if from >= self.len() || to >= self.len() || from == to {
+            return;
+        }
+        let current = self.index;
+        let path = self.paths.remove(from);
+        self.paths.insert(to, path);
+        self.index = if current == from {
+            to
+        } else if from < current && current <= to {
+            current - 1
+        } else if to <= current && current < from {
+            current + 1
+        } else {
+            current
+        };
+        self.notify(Change::List);
+    }
+
+    /// Shuffle the paths, keeping the current image current.
+    pub fn shuffle(&mut self) {
+        if self.len() < 2 {
+            return;
+        }
+        let current = self.paths.swap_remove(self.index);
+        fastrand::shuffle(&mut self.paths);
+        self.paths.insert(self.index, current);
+        self.notify(Change::List);
+    }
+
+    /// Take the changes made since this was last called.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    fn notify(&mut self, change: Change) {
+        if !self.changes.contains(&change) {
+            self.changes.push(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(names: &[&str]) -> Playlist {
+        Playlist::new(names.iter().map(PathBuf::from).collect())
+    }
+
+    fn names(playlist: &Playlist) -> Vec<&str> {
+        playlist
+            .paths()
+            .iter()
+            .map(|path| path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn insert_keeps_current() {
+        let mut list = playlist(&["a", "b", "c"]);
+        list.jump(1);
+        list.take_changes();
+
+        // Before the current image.
+        list.insert(0, ["x".into(), "y".into()]);
+        assert_eq!(names(&list), ["x", "y", "a", "b", "c"]);
+        assert_eq!(list.current(), Some(Path::new("b")));
+
+        // At the current image's position.
+        list.insert(3, ["z".into()]);
+        assert_eq!(list.current(), Some(Path::new("b")));
+
+        // After it, clamped to the end.
+        list.insert(100, ["w".into()]);
+        assert_eq!(names(&list), ["x", "y", "a", "z", "b", "c", "w"]);
+        assert_eq!(list.index(), 4);
+        assert_eq!(list.take_changes(), [Change::List]);
+
+        list.insert(0, []);
+        assert!(list.take_changes().is_empty());
+    }
+
+    #[test]
+    fn insert_into_empty() {
+        let mut list = playlist(&[]);
+        list.extend(["a".into(), "b".into()]);
+        assert_eq!(list.current(), Some(Path::new("a")));
+        assert_eq!(list.take_changes(), [Change::Current, Change::List]);
+    }
+
+    #[test]
+    fn remove_current() {
+        let mut list = playlist(&["a", "b", "c"]);
+        list.jump(1);
+        list.take_changes();
+        assert_eq!(list.remove(1), Some("b".into()));
+        assert_eq!(list.current(), Some(Path::new("c")));
+        assert_eq!(list.take_changes(), [Change::Current, Change::List]);
+    }
+
+    #[test]
+    fn remove_last() {
+        let mut list = playlist(&["a", "b", "c"]);
+        list.jump(2);
+        list.take_changes();
+
+        // The current image is last, so it wraps to the first.
+        list.remove(2);
+        assert_eq!(list.current(), Some(Path::new("a")));
+        assert_eq!(list.take_changes(), [Change::Current, Change::List]);
+
+        // Another image is last, so only the list changes.
+        list.remove(1);
+        assert_eq!(list.current(), Some(Path::new("a")));
+        assert_eq!(list.take_changes(), [Change::List]);
+
+        list.remove(0);
+        assert_eq!(list.current(), None);
+        assert_eq!(list.remove(0), None);
+    }
+
+    #[test]
+    fn remove_before_current() {
+        let mut list = playlist(&["a", "b", "c"]);
+        list.jump(2);
+        list.remove(0);
+        assert_eq!(list.index(), 1);
+        assert_eq!(list.current(), Some(Path::new("c")));
+    }
+
+    #[test]
+    fn reorder_forward() {
+        let mut list = playlist(&["a", "b", "c", "d"]);
+        list.jump(2);
+        list.reorder(0, 3);
+        assert_eq!(names(&list), ["b", "c", "d", "a"]);
+        assert_eq!(list.current(), Some(Path::new("c")));
+
+        list.reorder(1, 2);
+        assert_eq!(names(&list), ["b", "d", "c", "a"]);
+        assert_eq!(list.current(), Some(Path::new("c")));
+    }
+
+    #[test]
+    fn reorder_backward() {
+        let mut list = playlist(&["a", "b", "c", "d"]);
+        list.jump(1);
+        list.reorder(3, 0);
+        assert_eq!(names(&list), ["d", "a", "b", "c"]);
+        assert_eq!(list.current(), Some(Path::new("b")));
+
+        list.reorder(2, 1);
+        assert_eq!(names(&list), ["d", "b", "a", "c"]);
+        assert_eq!(list.current(), Some(Path::new("b")));
+    }
+
+    #[test]
+    fn reorder_out_of_range() {
+        let mut list = playlist(&["a", "b"]);
+        list.reorder(0, 2);
+        list.reorder(1, 1);
+        assert_eq!(names(&list), ["a", "b"]);
+        assert!(list.take_changes().is_empty());
+    }
+
+    #[test]
+    fn shuffle_keeps_current() {
+        let names: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        let mut list = Playlist::new(names.iter().map(PathBuf::from).collect());
+        list.jump(7);
+        list.take_changes();
+        list.shuffle();
+        assert_eq!(list.index(), 7);
+        assert_eq!(list.current(), Some(Path::new("7")));
+        assert_eq!(list.take_changes(), [Change::List]);
+
+        let mut shuffled = list.paths().to_vec();
+        shuffled.sort();
+        let mut sorted: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+        sorted.sort();
+        assert_eq!(shuffled, sorted);
+    }
+
+    #[test]
+    fn find_wraps_around() {
+        let list = playlist(&["dir/cat.png", "dog.jpg", "Cattle.gif", "bird.png"]);
+        assert_eq!(list.find("cat", 0), Some(0));
+        assert_eq!(list.find("CAT", 1), Some(2));
+        assert_eq!(list.find("cat", 3), Some(0));
+
+        // Only file names are searched.
+        assert_eq!(list.find("dir", 0), None);
+    }
+
+    #[test]
+    fn changes_are_deduplicated() {
+        let mut list = playlist(&["a", "b", "c"]);
+        list.next();
+        list.next();
+        list.reload();
+        list.extend(["d".into()]);
+        list.reorder(0, 1);
+        assert_eq!(list.take_changes(), [Change::Current, Change::List]);
+        assert!(list.take_changes().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::UserEvent;
+
+/// What a [`Pool`]'s workers do, and whatever's kept
+/// of their results (e.g. a cache).
+pub trait Work: Send + 'static {
+    type Job: Clone + Eq + Hash + Send;
+    type Output;
+
+    /// Do a job, without the pool locked.
+    fn run(job: &Self::Job) -> Self::Output;
+
+    /// Keep a finished job's output, returning the
+    /// event that tells the event loop it's done.
+    fn finish(&mut self, job: Self::Job, output: Self::Output) -> UserEvent;
+}
+
+/// A pool of worker threads taking jobs from a queue.
+///
+/// The queue and the [`Work`]'s state are behind one lock, so
+/// that what's queued can be decided by what's already done.
+pub struct Pool<W: Work> {
+    shared: Arc<Shared<W>>,
+}
+
+struct Shared<W: Work> {
+    jobs: Mutex<Jobs<W>>,
+    wake: Condvar,
+}
+
+pub struct Jobs<W: Work> {
+    /// Jobs waiting to be done, highest priority first.
+    pub queue: VecDeque<W::Job>,
+
+    /// Jobs currently being done.
+    pub in_flight: HashSet<W::Job>,
+
+    pub state: W,
+
+    is_running: bool,
+}
+impl<W: Work> Jobs<W> {
+    /// Whether a job is queued or being done.
+    pub fn is_pending(&self, job: &W::Job) -> bool {
+        self.in_flight.contains(job) || self.queue.contains(job)
+    }
+}
+
+impl<W: Work> Pool<W> {
+    pub fn new(state: W, proxy: EventLoopProxy<UserEvent>, workers: usize) -> Self {
+        let shared = Arc::new(Shared {
+            jobs: Mutex::new(Jobs {
+                queue: VecDeque::new(),
+                in_flight: HashSet::new(),
+                state,
+                is_running: true,
+            }),
+            wake: Condvar::new(),
+        });
+
+        for _ in 0..workers.max(1) {
+            let shared = Arc::clone(&shared);
+            let proxy = proxy.clone();
+            thread::spawn(move || work(&shared, &proxy));
+        }
+
+        Self { shared }
+    }
+
+    /// Lock the queue and state to look at or change them,
+    /// waking the workers afterwards if there's anything queued.
+    pub fn with<T>(&self, f: impl FnOnce(&mut Jobs<W>) -> T) -> T {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        let result = f(&mut jobs);
+        if !jobs.queue.is_empty() {
+            self.shared.wake.notify_all();
+        }
+        result
+    }
+}
+impl<W: Work> Drop for Pool<W> {
+    fn drop(&mut self) {
+        // Workers aren't joined as they may be in the middle
+        // of a long job; they'll exit once they finish.
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        jobs.is_running = false;
+        jobs.queue.clear();
+        self.shared.wake.notify_all();
+    }
+}
+
+fn work<W: Work>(shared: &Shared<W>, proxy: &EventLoopProxy<UserEvent>) {
+    loop {
+        let job = {
+            let mut jobs = shared.jobs.lock().unwrap();
+            loop {
+                if !jobs.is_running {
+                    return;
+                }
+                if let Some(job) = jobs.queue.pop_front() {
+                    jobs.in_flight.insert(job.clone());
+                    break job;
+                }
+                jobs = shared.wake.wait(jobs).unwrap();
+            }
+        };
+
+        let output = W::run(&job);
+
+        let event = {
+            let mut jobs = shared.jobs.lock().unwrap();
+            jobs.in_flight.remove(&job);
+            jobs.state.finish(job, output)
+        };
+
+        if proxy.send_event(event).is_err() {
+            return;
+        }
+    }
+}
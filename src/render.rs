@@ -0,0 +1,610 @@
+use std::{fmt, str::FromStr};
+
+use pixels::wgpu;
+
+/// How the image is resampled when zoomed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Crisp pixels, e.g. for pixel art.
+    #[default]
+    Nearest,
+    Bilinear,
+    CatmullRom,
+    Lanczos,
+}
+impl Filter {
+    const ALL: [Filter; 4] = [
+        Filter::Nearest,
+        Filter::Bilinear,
+        Filter::CatmullRom,
+        Filter::Lanczos,
+    ];
+
+    /// The next filter, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|f| *f == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// The value identifying this filter in the shader.
+    fn id(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Bilinear => "bilinear",
+            Filter::CatmullRom => "catmull-rom",
+            Filter::Lanczos => "lanczos",
+        }
+    }
+}
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|filter| filter.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "unknown filter `{s}`, expected one of: {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How the image is rotated and flipped when drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Orientation {
+    /// Quarter turns clockwise.
+    rotation: u8,
+
+    /// Whether it's mirrored horizontally after rotating.
+    flipped: bool,
+}
+impl Orientation {
+    /// Rotate a quarter turn clockwise (`true`) or counter-clockwise.
+    pub fn rotate(self, clockwise: bool) -> Self {
+        // Mirroring reverses the direction of the rotation
+        // that came before it.
+        let turn = if clockwise != self.flipped { 1 } else { 3 };
+        Self {
+            rotation: (self.rotation + turn) % 4,
+            ..self
+        }
+    }
+
+    /// Mirror horizontally (`true`) or vertically.
+    pub fn flip(self, horizontal: bool) -> Self {
+        // A vertical flip is a horizontal one and a half turn.
+        let turn = if horizontal { 0 } else { 2 };
+        Self {
+            rotation: (self.rotation + turn) % 4,
+            flipped: !self.flipped,
+        }
+    }
+
+    /// Whether the width and height are swapped.
+    pub fn is_transposed(self) -> bool {
+        self.rotation % 2 == 1
+    }
+
+    /// Map a normalized point in the image as drawn to the
+    /// unoriented image, as the shader does when sampling.
+    pub fn to_source(self, (mut x, mut y): (f32, f32)) -> (f32, f32) {
+        if self.flipped {
+            x = 1. - x;
+        }
+        for _ in 0..self.rotation {
+            (x, y) = (y, 1. - x);
+        }
+        (x, y)
+    }
+
+    /// The inverse of [`Orientation::to_source`].
+    pub fn to_drawn(self, (mut x, mut y): (f32, f32)) -> (f32, f32) {
+        for _ in 0..self.rotation {
+            (x, y) = (1. - y, x);
+        }
+        if self.flipped {
+            x = 1. - x;
+        }
+        (x, y)
+    }
+
+    /// The value identifying this orientation in the shader.
+    fn id(self) -> u32 {
+        self.rotation as u32 | (self.flipped as u32) << 2
+    }
+}
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.rotation, self.flipped) {
+            (0, false) => Ok(()),
+            (0, true) => f.write_str("flipped"),
+            (rotation, false) => write!(f, "rotated {}°", rotation as u32 * 90),
+            (rotation, true) => write!(f, "rotated {}° flipped", rotation as u32 * 90),
+        }
+    }
+}
+
+/// Draws the image (and any overlays) to the surface.
+///
+/// The image is uploaded once as a texture and zoom/pan
+/// are applied when sampling it, so changing the view
+/// doesn't require touching the pixel data.
+pub struct Renderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    clear_color: wgpu::Color,
+
+    image: Option<Layer>,
+
+    /// Part of the image at a higher resolution, drawn over
+    /// it; used for images too large for a single texture.
+    detail: Option<Layer>,
+
+    overlays: Vec<Layer>,
+}
+
+/// RGBA pixel data drawn unscaled over the image.
+pub struct Overlay<'a> {
+    pub data: &'a [u8],
+    pub size: (u32, u32),
+
+    /// Top-left corner in the viewport, in pixels.
+    pub origin: (f32, f32),
+}
+
+/// A texture drawn at some position and size in the viewport.
+struct Layer {
+    texture: wgpu::Texture,
+    locals: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+impl Renderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        clear_color: wgpu::Color,
+    ) -> Self {
+        let module = device.create_shader_module(wgpu::include_wgsl!("view.wgsl"));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("vu_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(LOCALS_SIZE),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("vu_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("vu_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            clear_color,
+            image: None,
+            detail: None,
+            overlays: vec![],
+        }
+    }
+
+    /// Allocate the texture for an image of the given size,
+    /// reusing the current one if it's the same size.
+    ///
+    /// Its data is set with [`Renderer::upload_image`].
+    pub fn set_image(&mut self, device: &wgpu::Device, size: (u32, u32)) -> anyhow::Result<()> {
+        let max_side = device.limits().max_texture_dimension_2d;
+        if size.0 > max_side || size.1 > max_side {
+            anyhow::bail!(
+                "Image is too large to display ({}x{}, max side is {max_side})",
+                size.0,
+                size.1
+            );
+        }
+        let layer = match self.image.take() {
+            Some(layer) if layer.size == size => layer,
+            _ => Layer::new(device, &self.bind_group_layout, size),
+        };
+        self.image = Some(layer);
+        Ok(())
+    }
+
+    pub fn upload_image(&self, queue: &wgpu::Queue, data: &[u8]) {
+        if let Some(image) = &self.image {
+            image.upload(queue, data);
+        }
+    }
+
+    /// Position the image in the viewport; `size` is
+    /// the size it's drawn at, after orienting it.
+    pub fn place_image(
+        &self,
+        queue: &wgpu::Queue,
+        origin: (f32, f32),
+        size: (f32, f32),
+        filter: Filter,
+        orientation: Orientation,
+    ) {
+        if let Some(image) = &self.image {
+            image.place(queue, origin, size, filter, orientation);
+        }
+    }
+
+    /// Set the detail layer's RGBA pixel data.
+    pub fn set_detail(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        size: (u32, u32),
+    ) -> anyhow::Result<()> {
+        let max_side = device.limits().max_texture_dimension_2d;
+        if size.0 > max_side || size.1 > max_side {
+            anyhow::bail!("Region is too large to display ({}x{})", size.0, size.1);
+        }
+        let layer = match self.detail.take() {
+            Some(layer) if layer.size == size => layer,
+            _ => Layer::new(device, &self.bind_group_layout, size),
+        };
+        layer.upload(queue, data);
+        self.detail = Some(layer);
+        Ok(())
+    }
+
+    /// Position the detail layer, as with [`Renderer::place_image`].
+    pub fn place_detail(
+        &self,
+        queue: &wgpu::Queue,
+        origin: (f32, f32),
+        size: (f32, f32),
+        filter: Filter,
+        orientation: Orientation,
+    ) {
+        if let Some(detail) = &self.detail {
+            detail.place(queue, origin, size, filter, orientation);
+        }
+    }
+
+    pub fn clear_detail(&mut self) {
+        self.detail = None;
+    }
+
+    /// Set the overlays drawn over the image, in order.
+    pub fn set_overlays(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        overlays: &[Overlay<'_>],
+    ) {
+        let mut layers = std::mem::take(&mut self.overlays).into_iter();
+        self.overlays = overlays
+            .iter()
+            .map(|&Overlay { data, size, origin }| {
+                // Reuse the existing textures where possible.
+                let layer = match layers.next() {
+                    Some(layer) if layer.size == size => layer,
+                    _ => Layer::new(device, &self.bind_group_layout, size),
+                };
+                layer.upload(queue, data);
+                let layer_size = (size.0 as f32, size.1 as f32);
+                layer.place(
+                    queue,
+                    origin,
+                    layer_size,
+                    Filter::Nearest,
+                    Orientation::default(),
+                );
+                layer
+            })
+            .collect();
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let layers = self.image.iter().chain(&self.detail).chain(&self.overlays);
+        self.draw(
+            encoder,
+            target,
+            wgpu::LoadOp::Clear(self.clear_color),
+            layers,
+        );
+    }
+
+    /// Draw just the overlays, over whatever's
+    /// already been drawn to the target.
+    pub fn render_overlays(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        self.draw(encoder, target, wgpu::LoadOp::Load, self.overlays.iter());
+    }
+
+    fn draw<'a>(
+        &'a self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        layers: impl Iterator<Item = &'a Layer>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("vu_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        for layer in layers {
+            pass.set_bind_group(0, &layer.bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+/// Size of the `Locals` uniform in the shader,
+/// padded to a multiple of 16 bytes.
+const LOCALS_SIZE: u64 = 8 * 4;
+
+impl Layer {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, size: (u32, u32)) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("vu_layer_texture"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let locals = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("vu_layer_locals"),
+            size: LOCALS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("vu_layer_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: locals.as_entire_binding(),
+                },
+            ],
+        });
+        Self {
+            texture,
+            locals,
+            bind_group,
+            size,
+        }
+    }
+
+    /// Write RGBA pixel data to the texture.
+    fn upload(&self, queue: &wgpu::Queue, data: &[u8]) {
+        let (width, height) = self.size;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
+            },
+            self.texture.size(),
+        );
+    }
+
+    fn place(
+        &self,
+        queue: &wgpu::Queue,
+        origin: (f32, f32),
+        size: (f32, f32),
+        filter: Filter,
+        orientation: Orientation,
+    ) {
+        let mut locals: Vec<u8> = [origin.0, origin.1, size.0, size.1]
+            .iter()
+            .flat_map(|v| v.to_ne_bytes())
+            .collect();
+        locals.extend(filter.id().to_ne_bytes());
+        locals.extend(orientation.id().to_ne_bytes());
+        locals.resize(LOCALS_SIZE as usize, 0);
+        queue.write_buffer(&self.locals, 0, &locals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: [u8; 4] = [0, 0, 0, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    /// A device on a software adapter, if there is one.
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            force_fallback_adapter: true,
+            ..Default::default()
+        }))?;
+        pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+    }
+
+    /// Render to a texture of the given size and read its pixels back.
+    fn render(
+        renderer: &Renderer,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        (width, height): (u32, u32),
+    ) -> Vec<[u8; 4]> {
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // Rows are copied out padded to the required alignment.
+        let row_len = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (row_len * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        renderer.render(&mut encoder, &target.create_view(&Default::default()));
+        encoder.copy_texture_to_buffer(
+            target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(row_len),
+                    rows_per_image: Some(height),
+                },
+            },
+            target.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+        buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+
+        let data = buffer.slice(..).get_mapped_range();
+        data.chunks(row_len as usize)
+            .flat_map(|row| row[..(width * 4) as usize].chunks(4))
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn renders_zoomed_and_panned() {
+        let Some((device, queue)) = device() else {
+            eprintln!("Skipping: no fallback adapter");
+            return;
+        };
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let clear = wgpu::Color {
+            r: 0.,
+            g: 0.,
+            b: 0.,
+            a: 1.,
+        };
+        let mut renderer = Renderer::new(&device, format, clear);
+
+        // A 4x4 image with a different color in each quarter.
+        renderer.set_image(&device, (4, 4)).unwrap();
+        let source = |x: i32, y: i32| match (x < 2, y < 2) {
+            (true, true) => RED,
+            (false, true) => GREEN,
+            (true, false) => BLUE,
+            (false, false) => WHITE,
+        };
+        let data: Vec<u8> = (0..16).flat_map(|i| source(i % 4, i / 4)).collect();
+        renderer.upload_image(&queue, &data);
+
+        // Zoomed in 2x and panned 2 pixels left,
+        // leaving the right edge uncovered.
+        renderer.place_image(
+            &queue,
+            (-2., 0.),
+            (8., 8.),
+            Filter::Nearest,
+            Orientation::default(),
+        );
+        let pixels = render(&renderer, &device, &queue, (8, 8));
+        for (i, pixel) in pixels.iter().enumerate() {
+            let (x, y) = (i as i32 % 8, i as i32 / 8);
+            let (source_x, source_y) = ((x + 2) / 2, y / 2);
+            let expected = if source_x < 4 {
+                source(source_x, source_y)
+            } else {
+                CLEAR
+            };
+            assert_eq!(*pixel, expected, "pixel at {x}, {y}");
+        }
+    }
+}
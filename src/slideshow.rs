@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+/// Moves on to the next image on a timer.
+///
+/// The event loop waits until the [`Slideshow::deadline`] and
+/// then calls [`Slideshow::tick`]. Animations are played through
+/// at least once before moving on, which the viewer reports
+/// with [`Slideshow::played`].
+///
+/// A paused slideshow doesn't time images until it's resumed.
+pub struct Slideshow {
+    interval: Duration,
+    is_paused: bool,
+
+    /// When the current image's time is up, if it's being timed.
+    deadline: Option<Instant>,
+
+    /// Whether the current image has been shown for the
+    /// interval, and whether it's been played through
+    /// (which still images always have).
+    is_timed_out: bool,
+    is_played: bool,
+}
+
+impl Slideshow {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            is_paused: false,
+            deadline: None,
+            is_timed_out: false,
+            is_played: false,
+        }
+    }
+
+    /// Start timing a newly-shown image, unless paused.
+    pub fn start(&mut self, is_animated: bool) {
+        self.is_timed_out = false;
+        self.is_played = !is_animated;
+        self.deadline = (!self.is_paused).then(|| Instant::now() + self.interval);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Stop timing images until resumed.
+    pub fn pause(&mut self) {
+        self.is_paused = true;
+        self.deadline = None;
+    }
+
+    /// Carry on from the current image, giving it the full interval.
+    pub fn resume(&mut self, is_animated: bool) {
+        self.is_paused = false;
+        self.start(is_animated);
+    }
+
+    /// When the current image's time is up, if it's being timed.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Check whether the current image's time is up by `now`;
+    /// returns whether to go to the next image.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                self.deadline = None;
+                self.is_timed_out = true;
+                self.take_done()
+            }
+            _ => false,
+        }
+    }
+
+    /// The current animation has played through;
+    /// returns whether to go to the next image.
+    pub fn played(&mut self) -> bool {
+        self.is_played = true;
+        self.take_done()
+    }
+
+    /// Whether the current image is done, resetting it so
+    /// that nothing else is skipped until the next image
+    /// is shown (it may take a while to load).
+    fn take_done(&mut self) -> bool {
+        let is_done = self.is_timed_out && self.is_played;
+        if is_done {
+            self.is_timed_out = false;
+            self.is_played = false;
+        }
+        is_done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pauses_and_resumes() {
+        let interval = Duration::from_secs(5);
+        let mut slideshow = Slideshow::new(interval);
+        slideshow.start(false);
+        let later = Instant::now() + interval * 2;
+
+        slideshow.pause();
+        assert_eq!(slideshow.deadline(), None);
+        assert!(!slideshow.tick(later));
+
+        // Images shown while paused aren't timed.
+        slideshow.start(false);
+        assert_eq!(slideshow.deadline(), None);
+        assert!(!slideshow.played());
+
+        slideshow.resume(false);
+        assert!(!slideshow.is_paused());
+        assert!(slideshow.deadline().is_some());
+        assert!(slideshow.tick(later));
+    }
+
+    #[test]
+    fn waits_for_animations_to_play() {
+        let interval = Duration::from_secs(5);
+        let mut slideshow = Slideshow::new(interval);
+        slideshow.start(true);
+        assert!(!slideshow.tick(Instant::now() + interval * 2));
+        assert!(slideshow.played());
+    }
+}
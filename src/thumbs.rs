@@ -0,0 +1,235 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{Cursor, Write},
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use image::ImageFormat;
+use url::Url;
+use winit::event_loop::EventLoopProxy;
+
+use crate::{
+    UserEvent,
+    cache::Cache,
+    img::{self, Image},
+    pool::{Pool, Work},
+};
+
+/// Thumbnails fit within this size, which is the
+/// freedesktop spec's "normal" size.
+pub const THUMB_SIZE: u32 = 128;
+
+/// Memory limit for the thumbnails kept in memory, in bytes
+/// (a thousand or so); the rest are read from disk again.
+const THUMB_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Generates thumbnails on a pool of worker threads,
+/// using the freedesktop thumbnail cache
+/// (`$XDG_CACHE_HOME/thumbnails/normal`) where possible.
+///
+/// A [`UserEvent::ThumbLoaded`] is emitted whenever
+/// a thumbnail is ready.
+pub struct Thumbnailer {
+    pool: Pool<State>,
+}
+
+struct State {
+    /// Thumbnails last requested, i.e. those in view. These are
+    /// never evicted to make room for other thumbnails.
+    wanted: HashSet<PathBuf>,
+
+    /// Finished thumbnails; `None` if one couldn't be generated.
+    thumbs: Cache<PathBuf, Option<Arc<Image>>>,
+}
+
+impl Thumbnailer {
+    pub fn new(proxy: EventLoopProxy<UserEvent>, workers: usize) -> Self {
+        let state = State {
+            wanted: HashSet::new(),
+            thumbs: Cache::new(THUMB_BUDGET),
+        };
+        Self {
+            pool: Pool::new(state, proxy, workers),
+        }
+    }
+
+    /// Get a thumbnail if it's ready.
+    pub fn get(&self, path: &Path) -> Option<Arc<Image>> {
+        self.pool
+            .with(|jobs| jobs.state.thumbs.get(&path.to_path_buf()).flatten())
+    }
+
+    /// Generate thumbnails for the given images, in order.
+    ///
+    /// This replaces any previously requested thumbnails
+    /// that haven't been started yet.
+    pub fn request(&self, paths: &[&Path]) {
+        self.pool.with(|jobs| {
+            jobs.queue.clear();
+            jobs.state.wanted = paths.iter().map(|path| path.to_path_buf()).collect();
+            for path in paths {
+                let path = path.to_path_buf();
+                if !jobs.state.thumbs.contains(&path) && !jobs.in_flight.contains(&path) {
+                    jobs.queue.push_back(path);
+                }
+            }
+        });
+    }
+}
+
+impl Work for State {
+    type Job = PathBuf;
+    type Output = Option<Arc<Image>>;
+
+    fn run(path: &PathBuf) -> Option<Arc<Image>> {
+        load_thumb(path).map(Arc::new)
+    }
+
+    fn finish(&mut self, path: PathBuf, thumb: Option<Arc<Image>>) -> UserEvent {
+        let size = thumb.as_ref().map_or(0, |thumb| thumb.byte_size());
+        if self.thumbs.make_room(size, &self.wanted) {
+            self.thumbs.insert(path.clone(), thumb, size);
+        }
+        UserEvent::ThumbLoaded(path)
+    }
+}
+
+/// Load a thumbnail from the cache,
+/// or generate (and cache) it if needed.
+fn load_thumb(path: &Path) -> Option<Image> {
+    let entry = CacheEntry::new(path);
+    if let Some(thumb) = entry.as_ref().and_then(CacheEntry::read) {
+        return Some(thumb);
+    }
+
+    let thumb = match img::read_thumbnail(path, THUMB_SIZE) {
+        Ok(thumb) => thumb,
+        Err(err) => {
+            eprintln!("Failed to generate thumbnail for {}: {err}", path.display());
+            return None;
+        }
+    };
+
+    // Caching is best-effort; the thumbnail
+    // can still be shown if it fails.
+    if let Some(entry) = entry {
+        let _ = entry.write(&thumb);
+    }
+    Some(thumb)
+}
+
+/// Where a file's thumbnail is cached, per the freedesktop
+/// thumbnail spec: the file is named after the MD5 hash of the
+/// image's URI, and records the URI and the image's modification
+/// time so that stale thumbnails can be detected.
+struct CacheEntry {
+    path: PathBuf,
+    uri: String,
+    mtime: u64,
+}
+impl CacheEntry {
+    /// `None` if the image can't be cached, e.g. if
+    /// it's from stdin or is itself a cached thumbnail.
+    fn new(image_path: &Path) -> Option<Self> {
+        let dir = cache_dir()?;
+        let image_path = image_path.canonicalize().ok()?;
+        if image_path.starts_with(dir.parent()?) {
+            return None;
+        }
+        let uri = Url::from_file_path(&image_path).ok()?.to_string();
+        let mtime = fs::metadata(&image_path)
+            .and_then(|meta| meta.modified())
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        let name = format!("{:x}.png", md5::compute(uri.as_bytes()));
+        Some(Self {
+            path: dir.join(name),
+            uri,
+            mtime,
+        })
+    }
+
+    /// Read the cached thumbnail if it's up-to-date, scaling it
+    /// down if another program cached it larger than we draw it.
+    fn read(&self) -> Option<Image> {
+        let bytes = fs::read(&self.path).ok()?;
+        let reader = png::Decoder::new(Cursor::new(&bytes)).read_info().ok()?;
+        let text = &reader.info().uncompressed_latin1_text;
+        let field = |keyword: &str| {
+            text.iter()
+                .find(|chunk| chunk.keyword == keyword)
+                .map(|chunk| chunk.text.as_str())
+        };
+        if field("Thumb::URI") != Some(&self.uri)
+            || field("Thumb::MTime") != Some(&self.mtime.to_string())
+        {
+            return None;
+        }
+
+        let mut thumb = image::load_from_memory_with_format(&bytes, ImageFormat::Png).ok()?;
+        if thumb.width() > THUMB_SIZE || thumb.height() > THUMB_SIZE {
+            thumb = thumb.thumbnail(THUMB_SIZE, THUMB_SIZE);
+        }
+        let thumb = thumb.into_rgba8();
+        let size = thumb.dimensions();
+        Some(Image::Single {
+            data: thumb.into_raw(),
+            size,
+            source_size: size,
+        })
+    }
+
+    /// Write the thumbnail to the cache.
+    ///
+    /// It's written to a temporary file first so that other
+    /// programs never see a partially-written thumbnail.
+    fn write(&self, thumb: &Image) -> anyhow::Result<()> {
+        let dir = self.path.parent().unwrap();
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+
+        let (width, height) = thumb.size();
+        let mut data = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut data, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.add_text_chunk("Thumb::URI".into(), self.uri.clone())?;
+            encoder.add_text_chunk("Thumb::MTime".into(), self.mtime.to_string())?;
+            encoder.add_text_chunk("Software".into(), "vu".into())?;
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(thumb.frame(0))?;
+        }
+
+        let tmp_path = self
+            .path
+            .with_extension(format!("png.vu-{}", std::process::id()));
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        file.write_all(&data)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// `$XDG_CACHE_HOME/thumbnails/normal`,
+/// falling back to `$HOME/.cache/thumbnails/normal`.
+fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("thumbnails").join("normal"))
+}
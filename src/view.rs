@@ -1,10 +1,18 @@
-use std::{path::Path, sync::OnceLock};
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex, OnceLock},
+};
 
-use crate::img::Image;
+use crate::{
+    UserEvent,
+    img::{self, Image, Pyramid, TILE_SIZE},
+    render::{Filter, Orientation, Overlay, Renderer},
+};
 use fontdue::{Font, FontSettings};
 use pixels::{Pixels, PixelsBuilder, SurfaceTexture, wgpu::Color};
 use winit::{
     dpi::{LogicalSize, PhysicalSize},
+    event_loop::EventLoopProxy,
     window::Window,
 };
 
@@ -21,10 +29,16 @@ const CLEAR_COLOR: Color = Color {
 const PAN_STEP: f32 = 0.1; // Percent of dimension
 const ZOOM_STEP: f32 = 0.1;
 const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 32.;
 
+#[derive(Clone)]
 pub struct ViewOpts {
     pub show_label: bool,
     pub label: String,
+    pub filter: Filter,
+
+    /// A prompt being typed into, e.g. to go to an image.
+    pub prompt: Option<String>,
 
     /// If `true`, the window will be resized to fit the image.
     /// If `false`, the image will be resized to fit the window.
@@ -32,52 +46,96 @@ pub struct ViewOpts {
     /// Note that resizing the window to fit the image can mess up
     /// the window positioning if it's already been positioned by the WM.
     pub resize_window: bool,
-    pub max_side: Option<u32>,
+}
+
+/// The maximum size an image should be decoded at
+/// for the monitor the window is on.
+pub fn max_bounds(window: &Window, max_side: Option<u32>) -> (u32, u32) {
+    let mon = window
+        .current_monitor()
+        .or_else(|| window.available_monitors().next())
+        .unwrap();
+    let scale_factor = mon.scale_factor();
+
+    let mon_size = mon.size();
+    match max_side {
+        Some(side) => {
+            let phys_side = (side as f64 * scale_factor).round() as u32;
+            (phys_side, phys_side)
+        }
+        None => (mon_size.width, mon_size.height),
+    }
 }
 
 pub struct ImageView {
     /// Current zoom level.
-    ///
-    /// We already scale down the image if needed to fit,
-    /// so we limit the minimum zoom to 1.0.
     zoom: f32,
 
     /// How the image is panned in the view, center-anchored.
     pan: (i32, i32),
 
-    /// What we draw the image to.
+    /// The surface we draw to.
+    ///
+    /// Its pixel buffer is only used for the gallery grid; the image
+    /// is kept in its own texture so that zooming and panning are
+    /// handled on the GPU.
     pixels: Pixels,
+    renderer: Renderer,
+
+    /// Whether the grid in the pixel buffer
+    /// is shown instead of the image.
+    show_grid: bool,
+
+    /// Size of the surface, in physical pixels.
+    viewport: (u32, u32),
+
+    /// How the image is resampled when zoomed.
+    filter: Filter,
+
+    /// How the image is rotated and flipped.
+    orientation: Orientation,
 
     /// The (source) image we're displaying.
-    pub image: Image,
+    ///
+    /// A still image may be scaled down to fit the monitor; the view
+    /// is laid out by its size even once the texture holds the
+    /// full-resolution image, which is drawn at the same size.
+    pub image: Arc<Image>,
+
+    /// Whether the texture holds the full-resolution image
+    /// (or it's been tried) rather than the scaled-down copy.
+    has_full_res: bool,
 
-    /// If the image is zoomed, we cache the scaled image here.
-    scaled: Option<Image>,
+    /// The full-resolution image, if it's too large for a
+    /// texture and so is drawn a region at a time.
+    tiled: Option<Tiled>,
+
+    /// Told when a region of a tiled image's tiles is loaded.
+    proxy: EventLoopProxy<UserEvent>,
 
     label: String,
     show_label: bool,
+
+    /// Extra info shown after the label, e.g. the playback state.
+    status: Option<String>,
+
+    /// Shown in the middle of the view.
+    message: Option<String>,
+
+    /// Shown in the bottom-left corner.
+    prompt: Option<String>,
 }
 impl ImageView {
-    pub fn new(image_path: &Path, window: &Window, opts: ViewOpts) -> anyhow::Result<Self> {
-        let mon = window
-            .current_monitor()
-            .or_else(|| window.available_monitors().next())
-            .unwrap();
-        let scale_factor = mon.scale_factor();
-
-        let mon_size = mon.size();
-        let max_bounds = match opts.max_side {
-            Some(side) => {
-                let phys_side = (side as f64 * scale_factor).round() as u32;
-                (phys_side, phys_side)
-            }
-            None => (mon_size.width, mon_size.height),
-        };
-
-        let image = crate::img::read_image(image_path, max_bounds)?;
+    pub fn new(
+        image: Arc<Image>,
+        window: &Window,
+        proxy: &EventLoopProxy<UserEvent>,
+        opts: ViewOpts,
+    ) -> anyhow::Result<Self> {
         let (mut width, mut height) = image.size();
 
         if opts.resize_window {
+            let scale_factor = window.scale_factor();
             let size = PhysicalSize::new(width as f64, height as f64);
             let size = LogicalSize::<f64>::from_physical(size, scale_factor);
             window
@@ -92,62 +150,344 @@ impl ImageView {
         }
 
         let surface_texture = SurfaceTexture::new(width, height, &window);
-        let pixels = PixelsBuilder::new(width, height, surface_texture)
+        let pixels = PixelsBuilder::new(1, 1, surface_texture)
             .clear_color(CLEAR_COLOR)
             .build()?;
+        let mut renderer =
+            Renderer::new(pixels.device(), pixels.render_texture_format(), CLEAR_COLOR);
+        renderer.set_image(pixels.device(), image.size())?;
 
         let mut view = Self {
             zoom: 1.,
             pan: (0, 0),
             pixels,
+            renderer,
+            show_grid: false,
+            viewport: (width, height),
+            filter: opts.filter,
+            orientation: Orientation::default(),
+            has_full_res: false,
+            tiled: Tiled::of(&image),
             image,
-            scaled: None,
+            proxy: proxy.clone(),
             label: opts.label,
             show_label: opts.show_label,
+            status: None,
+            message: None,
+            prompt: opts.prompt,
         };
+        view.has_full_res = view.tiled.is_some();
+        // A streamed sequence's first frame may not be ready; it's
+        // shown once it is by whoever's playing the sequence.
+        view.show_frame(0, || {});
 
         if !opts.resize_window {
             view.resize(width, height, true)?;
         } else {
+            view.update_overlays();
             view.update();
         }
 
         Ok(view)
     }
 
-    pub fn draw(&self) -> bool {
-        self.pixels.render().is_ok()
+    /// Show another image, fit to the view and unrotated.
+    ///
+    /// The surface and renderer are kept, so this only has
+    /// to allocate the image's texture (if its size changed).
+    pub fn set_image(&mut self, image: Arc<Image>) -> anyhow::Result<()> {
+        self.renderer
+            .set_image(self.pixels.device(), image.size())?;
+        self.image = image;
+        self.pan = (0, 0);
+        self.orientation = Orientation::default();
+        self.tiled = Tiled::of(&self.image);
+        self.has_full_res = self.tiled.is_some();
+        self.renderer.clear_detail();
+        self.status = None;
+        self.message = None;
+        self.show_frame(0, || {});
+
+        let (width, height) = self.viewport;
+        self.resize(width, height, true)
     }
 
-    /// Advance the image frame.
-    pub fn advance(&mut self) -> bool {
+    /// Show the same image decoded again (e.g. with other colors),
+    /// keeping the zoom, pan and orientation; the caller uploads
+    /// its frame. A full-resolution image is shown in its place
+    /// only once it's been decoded again too.
+    pub fn replace_image(&mut self, image: Arc<Image>) -> anyhow::Result<()> {
+        self.renderer
+            .set_image(self.pixels.device(), image.size())?;
+        self.image = image;
+        self.tiled = Tiled::of(&self.image);
+        self.has_full_res = self.tiled.is_some();
+        self.renderer.clear_detail();
         self.update();
-        self.draw()
+        Ok(())
+    }
+
+    pub fn draw(&self) -> bool {
+        self.pixels
+            .render_with(|encoder, target, context| {
+                if self.show_grid {
+                    context.scaling_renderer.render(encoder, target);
+                    self.renderer.render_overlays(encoder, target);
+                } else {
+                    self.renderer.render(encoder, target);
+                }
+                Ok(())
+            })
+            .is_ok()
+    }
+
+    /// Show an RGBA buffer (e.g. the gallery) in place of the image.
+    pub fn show_grid(&mut self, data: &[u8], (width, height): (u32, u32)) -> anyhow::Result<()> {
+        self.pixels.resize_buffer(width, height)?;
+        self.pixels.frame_mut().copy_from_slice(data);
+        self.show_grid = true;
+        self.draw();
+        Ok(())
+    }
+
+    /// Go back to showing the image.
+    pub fn hide_grid(&mut self) {
+        self.show_grid = false;
+        self.draw();
     }
 
-    /// Write the current transformed image view to the texture.
+    /// Upload an image frame to the texture, returning whether it
+    /// was ready; if not, the texture's left as it is and `ready` is
+    /// called once the frame's been decoded (see [`Image::with_frame`]).
+    pub fn show_frame(&mut self, index: usize, ready: impl FnOnce() + Send + 'static) -> bool {
+        let queue = self.pixels.queue();
+        self.image
+            .with_frame(index, |data| self.renderer.upload_image(queue, data), ready)
+    }
+
+    /// Whether the view is zoomed in past the detail in the
+    /// scaled-down image, so the full-resolution one should be shown.
+    pub fn wants_full_res(&self) -> bool {
+        !self.has_full_res && self.image.is_reduced() && self.zoom > 1.
+    }
+
+    /// The largest image the texture can hold.
+    pub fn max_image_size(&self) -> (u32, u32) {
+        let max_side = self.pixels.device().limits().max_texture_dimension_2d;
+        (max_side, max_side)
+    }
+
+    /// Sample from the full-resolution image instead of the
+    /// scaled-down one; the zoom and pan are unchanged.
+    ///
+    /// A tiled image is drawn over the scaled-down one, a region
+    /// at a time (see [`ImageView::show_loaded_tiles`]).
+    pub fn show_full_res(&mut self, loaded: Result<Arc<Image>, img::Error>) {
+        self.has_full_res = true;
+        let result = loaded.map_err(anyhow::Error::from).and_then(|full| {
+            if let Some(tiled) = Tiled::of(&full) {
+                self.tiled = Some(tiled);
+            } else {
+                self.renderer.set_image(self.pixels.device(), full.size())?;
+                self.renderer
+                    .upload_image(self.pixels.queue(), full.frame(0));
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                self.update();
+                self.draw();
+            }
+            Err(err) => eprintln!("Error loading full-resolution image: {err}"),
+        }
+    }
+
+    /// Update where the image is drawn for the current zoom and pan.
     fn update(&mut self) {
         self.clamp_pan();
-        let image = self.scaled.as_mut().unwrap_or(&mut self.image);
-        view_buffer_window(&mut self.pixels, image, self.pan);
+        let (vw, vh) = self.viewport;
+        let (im_w, im_h) = self.image_size();
+        let origin_x = (vw / 2) as i32 - (im_w / 2) as i32 - self.pan.0;
+        let origin_y = (vh / 2) as i32 - (im_h / 2) as i32 - self.pan.1;
+        self.renderer.place_image(
+            self.pixels.queue(),
+            (origin_x as f32, origin_y as f32),
+            (im_w as f32, im_h as f32),
+            self.filter,
+            self.orientation,
+        );
+        self.update_tiles(
+            (origin_x as f32, origin_y as f32),
+            (im_w as f32, im_h as f32),
+        );
+    }
 
-        if self.show_label {
-            self.draw_label();
+    /// Show the tiles covering the view, from the smallest level
+    /// with at least as much detail as the view, once they've been
+    /// loaded in the background.
+    ///
+    /// `origin` and `size` are where the whole image is drawn.
+    fn update_tiles(&mut self, origin: (f32, f32), size: (f32, f32)) {
+        let Some(tiled) = &mut self.tiled else {
+            return;
+        };
+        // The scaled-down image has enough detail by itself.
+        if self.zoom <= 1. {
+            tiled.region = None;
+            self.renderer.clear_detail();
+            return;
         }
+
+        // The part of the image in view, normalized.
+        let (vw, vh) = (self.viewport.0 as f32, self.viewport.1 as f32);
+        let visible = [
+            (-origin.0 / size.0).max(0.),
+            (-origin.1 / size.1).max(0.),
+            ((vw - origin.0) / size.0).min(1.),
+            ((vh - origin.1) / size.1).min(1.),
+        ];
+        if visible[0] < visible[2] && visible[1] < visible[3] {
+            let orientation = self.orientation;
+            let [x0, y0, x1, y1] = map_rect(visible, |p| orientation.to_source(p));
+
+            let pyramid = &tiled.pyramid;
+            let (width, height) = pyramid.size(0);
+            let width = if orientation.is_transposed() {
+                height
+            } else {
+                width
+            };
+            let scale = size.0 / width as f32;
+            let level =
+                ((1. / scale).log2().floor().max(0.) as usize).min(pyramid.level_count() - 1);
+            let (lw, lh) = pyramid.size(level);
+            let tiles = |from: f32, to: f32, side: u32| {
+                (from * side as f32).floor() as u32 / TILE_SIZE
+                    ..((to * side as f32).ceil() as u32).div_ceil(TILE_SIZE)
+            };
+            let region = Region {
+                level,
+                columns: tiles(x0, x1, lw),
+                rows: tiles(y0, y1, lh),
+            };
+
+            if tiled.region.as_ref() != Some(&region) {
+                let loaded = tiled.loaded.lock().unwrap().take();
+                if let Some((_, data, size)) = loaded.filter(|(loaded, ..)| *loaded == region) {
+                    match self.renderer.set_detail(
+                        self.pixels.device(),
+                        self.pixels.queue(),
+                        &data,
+                        size,
+                    ) {
+                        Ok(()) => tiled.region = Some(region),
+                        Err(err) => eprintln!("Error showing tiles: {err}"),
+                    }
+                } else {
+                    // Keep showing the previous region in the meantime.
+                    let loaded = Arc::clone(&tiled.loaded);
+                    let proxy = self.proxy.clone();
+                    let (columns, rows) = (region.columns.clone(), region.rows.clone());
+                    pyramid.load_region(level, columns, rows, move |data, size| {
+                        *loaded.lock().unwrap() = Some((region, data, size));
+                        let _ = proxy.send_event(UserEvent::TilesLoaded);
+                    });
+                }
+            }
+        }
+
+        if let Some(region) = &tiled.region {
+            let (lw, lh) = tiled.pyramid.size(region.level);
+            let source = [
+                (region.columns.start * TILE_SIZE) as f32 / lw as f32,
+                (region.rows.start * TILE_SIZE) as f32 / lh as f32,
+                (region.columns.end * TILE_SIZE).min(lw) as f32 / lw as f32,
+                (region.rows.end * TILE_SIZE).min(lh) as f32 / lh as f32,
+            ];
+            let orientation = self.orientation;
+            let [x0, y0, x1, y1] = map_rect(source, |p| orientation.to_drawn(p));
+            self.renderer.place_detail(
+                self.pixels.queue(),
+                (origin.0 + x0 * size.0, origin.1 + y0 * size.1),
+                ((x1 - x0) * size.0, (y1 - y0) * size.1),
+                self.filter,
+                orientation,
+            );
+        }
+    }
+
+    /// Show tiles that have finished loading.
+    pub fn show_loaded_tiles(&mut self) {
+        self.update();
+        self.draw();
     }
 
     pub fn resize(&mut self, width: u32, height: u32, fit_image: bool) -> anyhow::Result<()> {
         self.pixels.resize_surface(width, height)?;
-        self.pixels.resize_buffer(width, height)?;
+        self.viewport = (width.max(1), height.max(1));
+        self.update_overlays();
 
         if fit_image {
-            let (w, h) = self.image.size();
-            let zoom = (width as f32 / w as f32).min(height as f32 / h as f32);
-            self.set_zoom(zoom);
+            self.set_zoom(self.fit_zoom());
+        } else {
+            self.update();
         }
         Ok(())
     }
 
+    /// The zoom level at which the image fits the view.
+    fn fit_zoom(&self) -> f32 {
+        let (vw, vh) = self.viewport;
+        let (w, h) = self.oriented_size();
+        (vw as f32 / w as f32).min(vh as f32 / h as f32)
+    }
+
+    /// Zoom by a factor, keeping the image point at `anchor`
+    /// (in window coordinates) under it.
+    pub fn zoom_at(&mut self, factor: f32, (anchor_x, anchor_y): (f64, f64)) {
+        let max_zoom = MAX_ZOOM * self.actual_zoom();
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM.min(self.fit_zoom()), max_zoom);
+        if zoom == self.zoom {
+            return;
+        }
+
+        // Position of the anchor relative to the view center,
+        // and the scaled image point under it.
+        let (vw, vh) = self.viewport;
+        let (im_w, im_h) = self.image_size();
+        let offset_x = anchor_x as f32 - vw as f32 / 2.;
+        let offset_y = anchor_y as f32 - vh as f32 / 2.;
+        let point_x = (offset_x + im_w as f32 / 2. + self.pan.0 as f32) / self.zoom;
+        let point_y = (offset_y + im_h as f32 / 2. + self.pan.1 as f32) / self.zoom;
+
+        self.zoom = zoom;
+
+        let (im_w, im_h) = self.image_size();
+        self.pan.0 = (point_x * zoom - offset_x - im_w as f32 / 2.).round() as i32;
+        self.pan.1 = (point_y * zoom - offset_y - im_h as f32 / 2.).round() as i32;
+        self.update();
+        self.draw();
+    }
+
+    /// The zoom level at which the original image's pixels
+    /// are shown at their actual size.
+    fn actual_zoom(&self) -> f32 {
+        self.image.source_size().0 as f32 / self.image.size().0 as f32
+    }
+
+    /// Switch between fitting the image to the view and
+    /// showing it at its actual size.
+    pub fn toggle_fit(&mut self) {
+        let fit = self.fit_zoom();
+        if (self.zoom - fit).abs() < f32::EPSILON {
+            self.set_zoom(self.actual_zoom());
+        } else {
+            self.pan = (0, 0);
+            self.set_zoom(fit);
+        }
+    }
+
     pub fn zoom_in(&mut self) {
         self.set_zoom(self.zoom + ZOOM_STEP);
     }
@@ -158,7 +498,6 @@ impl ImageView {
 
     fn set_zoom(&mut self, zoom: f32) {
         self.zoom = zoom;
-        self.scaled = Some(self.image.scaled(self.zoom));
         self.update();
         self.draw();
     }
@@ -191,20 +530,62 @@ impl ImageView {
         self.draw();
     }
 
-    /// Get current image size.
-    ///
-    /// If the image is scaled, this will give the scaled size.
+    pub fn pan_by(&mut self, dx: i32, dy: i32) {
+        self.pan.0 += dx;
+        self.pan.1 += dy;
+        self.update();
+        self.draw();
+    }
+
+    /// Get the size the image is drawn at,
+    /// i.e. accounting for zoom and orientation.
     fn image_size(&self) -> (u32, u32) {
-        let image = self.scaled.as_ref().unwrap_or(&self.image);
-        image.size()
+        let (width, height) = self.oriented_size();
+        let width = (width as f32 * self.zoom).round() as u32;
+        let height = (height as f32 * self.zoom).round() as u32;
+        (width.max(1), height.max(1))
+    }
+
+    /// The image's size once rotated.
+    fn oriented_size(&self) -> (u32, u32) {
+        let (width, height) = self.image.size();
+        if self.orientation.is_transposed() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Rotate a quarter turn clockwise (`true`) or counter-clockwise.
+    pub fn rotate(&mut self, clockwise: bool) {
+        // Keep the same part of the image in the middle of the view.
+        let (x, y) = self.pan;
+        let pan = if clockwise { (-y, x) } else { (y, -x) };
+        self.reorient(self.orientation.rotate(clockwise), pan);
+    }
+
+    /// Mirror horizontally (`true`) or vertically.
+    pub fn flip(&mut self, horizontal: bool) {
+        let (x, y) = self.pan;
+        let pan = if horizontal { (-x, y) } else { (x, -y) };
+        self.reorient(self.orientation.flip(horizontal), pan);
+    }
+
+    fn reorient(&mut self, orientation: Orientation, pan: (i32, i32)) {
+        let was_fit = (self.zoom - self.fit_zoom()).abs() < f32::EPSILON;
+        self.orientation = orientation;
+        self.pan = pan;
+        let (width, height) = self.viewport;
+        if let Err(err) = self.resize(width, height, was_fit) {
+            eprintln!("Error resizing view: {err}");
+        }
+        self.draw();
     }
 
     /// Limit the pan to the view size.
     fn clamp_pan(&mut self) {
         let (im_w, im_h) = self.image_size();
-
-        let texture = self.pixels.texture();
-        let (tx_w, tx_h) = (texture.width(), texture.height());
+        let (tx_w, tx_h) = self.viewport;
 
         let x_limit = ((im_w as f32 / 2. - tx_w as f32 / 2.).floor() as i32).max(0);
         let y_limit = ((im_h as f32 / 2. - tx_h as f32 / 2.).floor() as i32).max(0);
@@ -214,55 +595,191 @@ impl ImageView {
 
     pub fn toggle_label(&mut self) {
         self.show_label = !self.show_label;
+        self.update_overlays();
+        self.draw();
+    }
+
+    pub fn cycle_filter(&mut self) {
+        self.filter = self.filter.next();
         self.update();
+        self.update_overlays();
         self.draw();
     }
 
-    pub fn is_label_visible(&self) -> bool {
-        self.show_label
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+        self.update_overlays();
+        self.draw();
     }
 
-    fn draw_label(&mut self) {
-        let font = FONT.get_or_init(|| {
-            let font_data = include_bytes!("../font.ttf") as &[u8];
-            Font::from_bytes(font_data, FontSettings::default()).expect("Failed to load font.ttf")
-        });
+    pub fn set_status(&mut self, status: Option<String>) {
+        if self.status != status {
+            self.status = status;
+            self.update_overlays();
+        }
+    }
 
-        let texture = self.pixels.texture();
-        let width = texture.width() as i32;
-        let height = texture.height() as i32;
-        let frame = self.pixels.frame_mut();
+    /// Show a message in the middle of the view,
+    /// e.g. why the image couldn't be loaded.
+    pub fn set_message(&mut self, message: Option<String>) {
+        self.message = message;
+        self.update_overlays();
+    }
 
-        let font_size = 20.0;
-        let padding = 15.0;
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.prompt = prompt;
+        self.update_overlays();
+        self.draw();
+    }
 
-        // Calculate the total width of the string to right-align it
-        let mut total_width = 0.0;
-        for c in self.label.chars() {
-            total_width += font.metrics(c, font_size).advance_width;
+    /// Redraw the label, message and prompt overlays,
+    /// leaving out the label if it's hidden.
+    fn update_overlays(&mut self) {
+        let (vw, vh) = (self.viewport.0 as f32, self.viewport.1 as f32);
+        let mut overlays = vec![];
+        if let Some(message) = &self.message {
+            let lines: Vec<_> = message.lines().collect();
+            let (data, (w, h)) = draw_text(&lines);
+
+            // Centered in the view.
+            let origin = (
+                ((vw - w as f32) / 2.).floor(),
+                ((vh - h as f32) / 2.).floor(),
+            );
+            overlays.push((data, (w, h), origin));
         }
+        if self.show_label {
+            let mut label = self.label.clone();
+            if let Some(status) = &self.status {
+                label.push(' ');
+                label.push_str(status);
+            }
+            if self.orientation != Orientation::default() {
+                label.push_str(&format!(" {}", self.orientation));
+            }
+            label.push_str(&format!(" [{}]", self.filter));
+            let (data, (w, h)) = draw_text(&[&label]);
 
-        let start_x = width as f32 - total_width - padding;
-        let start_y = height as f32 - font_size - padding;
+            // Anchored to the bottom-right corner.
+            let origin = (vw - w as f32 - LABEL_PADDING, vh - h as f32 - LABEL_PADDING);
+            overlays.push((data, (w, h), origin));
+        }
+        if let Some(prompt) = &self.prompt {
+            let (data, (w, h)) = draw_text(&[prompt]);
 
-        // Draw shadow
-        let passes = [
-            (2.0, 2.0, 0.0),   // Shadow: X offset, Y offset, Color
-            (0.0, 0.0, 255.0), // Text: X offset, Y offset, Color
-        ];
+            // Anchored to the bottom-left corner.
+            let origin = (LABEL_PADDING, vh - h as f32 - LABEL_PADDING);
+            overlays.push((data, (w, h), origin));
+        }
+
+        let overlays: Vec<_> = overlays
+            .iter()
+            .map(|(data, size, origin)| Overlay {
+                data,
+                size: *size,
+                origin: *origin,
+            })
+            .collect();
+        self.renderer
+            .set_overlays(self.pixels.device(), self.pixels.queue(), &overlays);
+    }
+}
+
+/// A tiled image being shown at full resolution.
+struct Tiled {
+    pyramid: Arc<Pyramid>,
 
-        for (offset_x, offset_y, color_val) in passes {
-            let mut cursor_x = start_x + offset_x;
-            let cursor_y = start_y + offset_y;
+    /// The tiles in the detail layer, if any.
+    region: Option<Region>,
 
-            for c in self.label.chars() {
+    /// The last region copied out of the pyramid in
+    /// the background, until it's uploaded.
+    loaded: Arc<Mutex<Option<LoadedRegion>>>,
+}
+impl Tiled {
+    fn of(image: &Image) -> Option<Self> {
+        match image {
+            Image::Tiled { pyramid, .. } => Some(Self {
+                pyramid: Arc::clone(pyramid),
+                region: None,
+                loaded: Arc::default(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A region with its pixels and size.
+type LoadedRegion = (Region, Vec<u8>, (u32, u32));
+
+#[derive(Clone, PartialEq)]
+struct Region {
+    level: usize,
+    columns: Range<u32>,
+    rows: Range<u32>,
+}
+
+/// Map a rectangle (left, top, right, bottom) through an
+/// orientation's mapping, which keeps it axis-aligned.
+fn map_rect([x0, y0, x1, y1]: [f32; 4], map: impl Fn((f32, f32)) -> (f32, f32)) -> [f32; 4] {
+    let (ax, ay) = map((x0, y0));
+    let (bx, by) = map((x1, y1));
+    [ax.min(bx), ay.min(by), ax.max(bx), ay.max(by)]
+}
+
+/// Distance of the label from the edges of the view.
+const LABEL_PADDING: f32 = 11.;
+
+/// Draw lines of text with a drop shadow to a transparent buffer
+/// just big enough to hold them, so that it's cheap to redraw
+/// (e.g. for every animation frame).
+///
+/// Returns the buffer and its size.
+fn draw_text(lines: &[&str]) -> (Vec<u8>, (u32, u32)) {
+    let font = FONT.get_or_init(|| {
+        let font_data = include_bytes!("../font.ttf") as &[u8];
+        Font::from_bytes(font_data, FontSettings::default()).expect("Failed to load font.ttf")
+    });
+
+    let font_size = 20.0;
+    let line_height = font_size * 1.5;
+
+    // Room around the text for the shadow,
+    // descenders and glyph overhangs.
+    let margin = 4.0;
+
+    let text_width = lines
+        .iter()
+        .map(|line| {
+            line.chars()
+                .map(|c| font.metrics(c, font_size).advance_width)
+                .sum::<f32>()
+        })
+        .fold(0., f32::max);
+
+    let width = (text_width + margin * 2.0 + 2.0).ceil() as i32;
+    let height = (line_height * lines.len() as f32 + margin * 2.0).ceil() as i32;
+    let mut frame = vec![0u8; (width * height * 4) as usize];
+
+    // Draw shadow
+    let passes = [
+        (2.0, 2.0, 0.0),   // Shadow: X offset, Y offset, Color
+        (0.0, 0.0, 255.0), // Text: X offset, Y offset, Color
+    ];
+
+    for (offset_x, offset_y, color_val) in passes {
+        for (line_index, line) in lines.iter().enumerate() {
+            let mut cursor_x = margin + offset_x;
+            let cursor_y = margin + offset_y + line_height * line_index as f32;
+
+            for c in line.chars() {
                 let (metrics, bitmap) = font.rasterize(c, font_size);
 
                 for (i, &coverage) in bitmap.iter().enumerate() {
                     let lx = (i % metrics.width) as i32;
                     let ly = (i / metrics.width) as i32;
 
-                    // Calculate screen pixel coordinates
+                    // Calculate buffer pixel coordinates
                     let px = cursor_x as i32 + metrics.xmin + lx;
                     // Font y-axis usually originates from the baseline
                     let py =
@@ -274,80 +791,24 @@ impl ImageView {
                         let idx = ((py * width + px) * 4) as usize;
                         let alpha = coverage as f32 / 255.0;
 
-                        // Blend RGB
+                        // Blend Alpha
+                        let bg_alpha = frame[idx + 3] as f32 / 255.0;
+                        let out_alpha = alpha + bg_alpha * (1.0 - alpha);
+                        frame[idx + 3] = (255.0 * out_alpha) as u8;
+
+                        // Blend RGB; the overlay is transparent
+                        // so the background's alpha is accounted for.
                         for color_channel in 0..3 {
                             let bg = frame[idx + color_channel] as f32;
                             frame[idx + color_channel] =
-                                ((color_val * alpha) + (bg * (1.0 - alpha))) as u8;
+                                ((color_val * alpha + bg * bg_alpha * (1.0 - alpha)) / out_alpha)
+                                    as u8;
                         }
-
-                        // Blend Alpha
-                        let bg_alpha = frame[idx + 3] as f32;
-                        frame[idx + 3] = ((255.0 * alpha) + (bg_alpha * (1.0 - alpha))) as u8;
                     }
                 }
                 cursor_x += metrics.advance_width;
             }
         }
     }
-}
-
-/// Extract a window on the image that fits into the surface texture area,
-/// accounting for any pan.
-fn view_buffer_window(pixels: &mut Pixels, image: &mut Image, pan: (i32, i32)) {
-    let texture = pixels.texture();
-    let (w, h) = (texture.width(), texture.height());
-    let size = image.size();
-    let data = image.next_frame();
-    let view = buffer_window(data, size, (w, h), pan);
-    pixels.frame_mut().copy_from_slice(&view);
-}
-
-/// Extract image data to fit into a window, with offset.
-fn buffer_window(
-    buffer: &[u8],
-    (img_width, img_height): (u32, u32),
-    (win_width, win_height): (u32, u32),
-    (offset_x, offset_y): (i32, i32), // Center-anchored offset
-) -> Vec<u8> {
-    // Assumes RGBA (i.e. 4 channels).
-    const CHANNELS: usize = 4;
-
-    // Padding for when `img` is smaller than `win`, to keep it centered.
-    let padding_x = (win_width.saturating_sub(img_width) / 2) as usize;
-    let padding_y = (win_height.saturating_sub(img_height) / 2) as usize;
-
-    // Range of pixels to copy from the image, based on the window and any pan offset.
-    let (start_x, end_x) = centered_span(img_width as i32, win_width as i32, offset_x);
-    let (start_y, end_y) = centered_span(img_height as i32, win_height as i32, offset_y);
-
-    // Copy the in-window image pixels.
-    let end_y = end_y.min(img_height as usize);
-    let slice_width = (end_x - start_x).min(img_width as usize);
-    let mut result = vec![0u8; (win_width * win_height) as usize * CHANNELS];
-    for (i, y) in (start_y..end_y).enumerate() {
-        let a = flat_idx(start_x, y, img_width as usize) * CHANNELS;
-        let b = a + slice_width * CHANNELS;
-        let im_row = &buffer[a..b];
-
-        let x = padding_x;
-        let y = padding_y + i;
-        let idx = flat_idx(x, y, win_width as usize) * CHANNELS;
-        let end_idx = idx + slice_width * CHANNELS;
-        result[idx..end_idx].copy_from_slice(im_row);
-    }
-
-    result
-}
-
-fn flat_idx(x: usize, y: usize, w: usize) -> usize {
-    y * w + x
-}
-
-fn centered_span(img_dim: i32, win_dim: i32, offset: i32) -> (usize, usize) {
-    let img_center = img_dim / 2 + offset;
-    let win_center = win_dim / 2;
-    let start = (img_center - win_center).max(0).min(img_dim) as usize;
-    let end = start + win_dim as usize;
-    (start, end)
+    (frame, (width as u32, height as u32))
 }
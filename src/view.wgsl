@@ -0,0 +1,128 @@
+struct Locals {
+    // Top-left corner of the layer in the viewport, in pixels.
+    origin: vec2<f32>,
+
+    // Size the layer is drawn at, in pixels.
+    size: vec2<f32>,
+
+    // Resampling filter: 0 = nearest, 1 = bilinear,
+    // 2 = Catmull-Rom, 3 = Lanczos3.
+    resampling: u32,
+
+    // Quarter turns clockwise in the low two bits;
+    // bit 2 mirrors horizontally after rotating.
+    orientation: u32,
+}
+
+const PI: f32 = 3.14159265;
+
+@group(0) @binding(0) var tex: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> locals: Locals;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // One full-screen triangle.
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let view_uv = (position.xy - locals.origin) / locals.size;
+    if any(view_uv < vec2<f32>(0.0)) || any(view_uv >= vec2<f32>(1.0)) {
+        discard;
+    }
+    let uv = orient(view_uv);
+
+    // Position in the texture, relative to texel centers.
+    let dims = vec2<f32>(textureDimensions(tex));
+    let pos = uv * dims - 0.5;
+
+    switch locals.resampling {
+        case 1u: {
+            return resample(pos, 1);
+        }
+        case 2u: {
+            return resample(pos, 2);
+        }
+        case 3u: {
+            return resample(pos, 3);
+        }
+        default: {
+            return texel(vec2<i32>(uv * dims));
+        }
+    }
+}
+
+// Map a position in the layer as drawn to
+// the texture, undoing the orientation.
+fn orient(uv: vec2<f32>) -> vec2<f32> {
+    var pos = uv;
+    if (locals.orientation & 4u) != 0u {
+        pos.x = 1.0 - pos.x;
+    }
+    for (var i = 0u; i < (locals.orientation & 3u); i += 1u) {
+        pos = vec2<f32>(pos.y, 1.0 - pos.x);
+    }
+    return pos;
+}
+
+// Load a texel, premultiplied so that filtering
+// doesn't bleed color from transparent pixels.
+fn texel(pos: vec2<i32>) -> vec4<f32> {
+    let max_pos = vec2<i32>(textureDimensions(tex)) - 1;
+    let color = textureLoad(tex, clamp(pos, vec2<i32>(0), max_pos), 0);
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
+
+// Sample with the current filter's kernel over
+// the texels within `radius` of `pos`.
+fn resample(pos: vec2<f32>, radius: i32) -> vec4<f32> {
+    let base = floor(pos);
+    let frac = pos - base;
+
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var y = 1 - radius; y <= radius; y += 1) {
+        let wy = kernel(f32(y) - frac.y);
+        for (var x = 1 - radius; x <= radius; x += 1) {
+            let w = kernel(f32(x) - frac.x) * wy;
+            sum += w * texel(vec2<i32>(base) + vec2<i32>(x, y));
+            total += w;
+        }
+    }
+
+    // Negative lobes can overshoot.
+    let color = clamp(sum / total, vec4<f32>(0.0), vec4<f32>(1.0));
+    return vec4<f32>(min(color.rgb, vec3<f32>(color.a)), color.a);
+}
+
+fn kernel(offset: f32) -> f32 {
+    let x = abs(offset);
+    switch locals.resampling {
+        case 2u: {
+            // Catmull-Rom (B = 0, C = 0.5)
+            if x < 1.0 {
+                return (1.5 * x - 2.5) * x * x + 1.0;
+            } else if x < 2.0 {
+                return ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0;
+            }
+            return 0.0;
+        }
+        case 3u: {
+            // Lanczos3
+            if x < 1e-5 {
+                return 1.0;
+            } else if x < 3.0 {
+                let px = PI * x;
+                return 3.0 * sin(px) * sin(px / 3.0) / (px * px);
+            }
+            return 0.0;
+        }
+        default: {
+            // Bilinear
+            return max(1.0 - x, 0.0);
+        }
+    }
+}